@@ -59,7 +59,7 @@ export interface EButton {
 export interface ESignal {
   kind: 'signal';
   net: string;
-  pattern: ('0' | '1' | 'z')[];
+  pattern: ('0' | '1' | 'z' | 'l' | 'h' | 'x')[];
   repeat: boolean;
 }
 
@@ -90,54 +90,6 @@ function builtinRead(x: string) {
   return Sk.builtinFiles["files"][x];
 }
 
-class UnionFind<Key> {
-  weights = new Map<Key, number>();
-  parents = new Map<Key, Key>();
-
-  canonicalize(k: Key): Key {
-    if (!this.weights.has(k)) {
-      this.weights.set(k, 1);
-      this.parents.set(k, k);
-    }
-
-    const path = [k];
-    let root = this.parents.get(k)!;
-    while (root !== path[path.length - 1]) {
-      path.push(root);
-      root = this.parents.get(root)!;
-    }
-
-    for (const node of path)
-      this.parents.set(node, root);
-    return root;
-  }
-
-  union(...keys: Key[]) {
-    if (keys.length === 0)
-      return;
-    const roots = keys.map((k) => this.canonicalize(k));
-    // Find the heaviest.
-    let totalWeight = 0;
-    let heaviest = null as unknown as Key;
-    let heaviestWeight = 0;
-    for (const root of roots) {
-      const weight = this.weights.get(root)!;
-      totalWeight += weight;
-      if (weight > heaviestWeight) {
-        heaviest = root;
-        heaviestWeight = weight;
-      }
-    }
-    // Reweight and reparent.
-    this.weights.set(heaviest!, totalWeight);
-    for (const root of roots) {
-      if (root !== heaviest) {
-        this.parents.set(root, heaviest);
-      }
-    }
-  }
-}
-
 function renderTraces(level: ILevel, simResults: ISimResults, grading: IGrading) {
   const width = 800;
   const height = 30 * (simResults.probes.length + 1);
@@ -394,7 +346,6 @@ class App extends React.PureComponent<{}, IAppState> {
     const nets = new Set<string>(['vdd', 'gnd']);
     const tracedNets = new Set<string>();
     const probes: EProbe[] = [];
-    const netCanonicalizer = new UnionFind<string>();
     for (const component of components) {
       switch (component.kind) {
         case 'fet':
@@ -404,7 +355,6 @@ class App extends React.PureComponent<{}, IAppState> {
         case 'wire':
           for (const net of component.nets)
             nets.add(net);
-          netCanonicalizer.union(...component.nets);
           break;
         case 'probe':
           probes.push(component);
@@ -434,11 +384,10 @@ class App extends React.PureComponent<{}, IAppState> {
     const descArray: number[] = [];
     const netIndices = new Map<string, number>();
     for (const net of nets) {
-      const canonicalizedNet = netCanonicalizer.canonicalize(net);
-      if (!netIndices.has(canonicalizedNet)) {
-        netIndices.set(canonicalizedNet, netIndices.size);
-      }
-      netIndices.set(net, netIndices.get(canonicalizedNet)!);
+      // Name every net, so the simulator can report diagnostics and pick canonical nets by name.
+      const name = [...net].map((c) => c.codePointAt(0)!);
+      descArray.push(14, netIndices.size, name.length, ...name, 123456789);
+      netIndices.set(net, netIndices.size);
     }
     for (const component of components) {
       switch (component.kind) {
@@ -457,7 +406,7 @@ class App extends React.PureComponent<{}, IAppState> {
             netIndices.get(component.net)!,
             +component.repeat,
             component.pattern.length,
-            ...[...component.pattern].map((c) => ({'0': 0, '1': 1, 'z': 2}[c])),
+            ...[...component.pattern].map((c) => ({'0': 0, '1': 1, 'z': 2, 'l': 3, 'h': 4, 'x': 5}[c])),
           );
           break;
         case 'pull_resistor':
@@ -480,6 +429,14 @@ class App extends React.PureComponent<{}, IAppState> {
             ...component.bus_out_nets.map((net) => netIndices.get(net)!),
           );
           break;
+        case 'wire':
+          // Wired nets are merged inside the simulator.
+          descArray.push(
+            15,
+            component.nets.length,
+            ...component.nets.map((net) => netIndices.get(net)!),
+          );
+          break;
         default:
           continue;
       }
@@ -663,8 +620,8 @@ class App extends React.PureComponent<{}, IAppState> {
       if (repeat)
         pattern = pattern.slice(0, -3);
       for (const c of pattern)
-        if (c !== '0' && c !== '1' && c !== 'z')
-          throw 'The pattern must be made of 0s, 1s and zs, and may optionally end with ... to indicate that the pattern should repeat.';
+        if (!'01zlhx'.includes(c))
+          throw 'The pattern must be made of 0s, 1s and zs (or l and h for weak low and high, and x for unknown), and may optionally end with ... to indicate that the pattern should repeat.';
       components.push({ kind: 'signal', net, pattern, repeat });
       return Sk.ffi.remapToPy(net);
     };
@@ -0,0 +1,310 @@
+
+use super::{Component, DriveType, Net, NetState};
+
+// A pull-up or pull-down network of same-polarity FETs between a source net (usually a rail) and the cell output.
+// Every internal node of a series chain is only ever driven one way or left floating, so its state is just a flag:
+// bit i of internal is set when the node just below gates[i] is at the rail level.
+struct SwitchNetwork {
+  is_pfet: bool,
+  is_series: bool,
+  // Parallel gates in any order, or series gates from the output down to the source.
+  gates: Vec<Net>,
+  source: Net,
+  internal: u32,
+}
+
+impl SwitchNetwork {
+  // Returns whether the network is driving the output this tick, and steps the internal nodes forward.
+  fn step(&mut self, net_states: &[NetState]) -> bool {
+    let (on_level, rail_state) = match self.is_pfet {
+      false => (NetState::High, NetState::Low),
+      true => (NetState::Low, NetState::High),
+    };
+    let source_at_rail = net_states[self.source as usize] == rail_state;
+    if !self.is_series {
+      return source_at_rail && self.gates.iter().any(|gate| net_states[*gate as usize] == on_level);
+    }
+    // Each node (and the output) is at the rail next tick if the FET below it is on and the node below that is at the rail now.
+    let bottom = self.gates.len() - 1;
+    let below_at_rail = |i: usize| match i == bottom {
+      true => source_at_rail,
+      false => self.internal & (1 << i) != 0,
+    };
+    let mut next_internal: u32 = 0;
+    let mut driving = false;
+    for (i, gate) in self.gates.iter().enumerate() {
+      if net_states[*gate as usize] == on_level && below_at_rail(i) {
+        match i {
+          0 => driving = true,
+          _ => next_internal |= 1 << (i - 1),
+        }
+      }
+    }
+    self.internal = next_internal;
+    driving
+  }
+}
+
+// A textbook static CMOS inverter, NAND or NOR recognized in the FET netlist. It evaluates its FETs exactly as the
+// main loop would, including the tick of delay each series FET adds, but as one component with private internal nodes.
+pub struct CmosCell {
+  pub output: Net,
+  pull_up: SwitchNetwork,
+  pull_down: SwitchNetwork,
+}
+
+impl CmosCell {
+  pub fn nets(&self) -> Vec<Net> {
+    let mut nets = vec![self.output, self.pull_up.source, self.pull_down.source];
+    nets.extend(self.inputs());
+    nets
+  }
+
+  // The gates of every FET in the cell.
+  pub fn inputs(&self) -> Vec<Net> {
+    [&self.pull_up.gates[..], &self.pull_down.gates[..]].concat()
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    self.output = f(self.output);
+    for network in [&mut self.pull_up, &mut self.pull_down].iter_mut() {
+      network.source = f(network.source);
+      for gate in network.gates.iter_mut() {
+        *gate = f(*gate);
+      }
+    }
+  }
+
+  pub fn step(&mut self, net_states: &[NetState]) -> DriveType {
+    match (self.pull_down.step(net_states), self.pull_up.step(net_states)) {
+      (false, false) => DriveType::HighZ,
+      (true, false) => DriveType::Low,
+      (false, true) => DriveType::High,
+      (true, true) => DriveType::ShootThrough,
+    }
+  }
+}
+
+// The FETs with a given drain, and how a network of them is shaped.
+enum Shape {
+  // Several FETs from one source net straight to the output.
+  Parallel { source: Net, fets: Vec<usize> },
+  // FETs in series, listed from the output down to the source net.
+  Series { source: Net, fets: Vec<usize> },
+}
+
+impl Shape {
+  fn fets(&self) -> &[usize] {
+    match self {
+      Shape::Parallel { fets, .. } | Shape::Series { fets, .. } => fets,
+    }
+  }
+
+  fn into_network(self, is_pfet: bool, components: &[Component]) -> SwitchNetwork {
+    let gate_of = |index: &usize| match components[*index] {
+      Component::Fet{ gate, .. } => gate,
+      _ => unreachable!(),
+    };
+    let (is_series, source, fets) = match self {
+      Shape::Parallel { source, fets } => (false, source, fets),
+      Shape::Series { source, fets } => (true, source, fets),
+    };
+    SwitchNetwork { is_pfet, is_series, gates: fets.iter().map(gate_of).collect(), source, internal: 0 }
+  }
+}
+
+// Replaces every textbook CMOS inverter, NAND and NOR made of FETs with a single CmosCell.
+// A net counts as observable if it's traced or has anything other than the simulator's plain behavior attached,
+// and only nets that nothing outside the gate can observe get folded inside a cell, so all other nets keep identical traces.
+pub fn abstract_gates(components: &mut Vec<Component>, observable: &[bool]) {
+  let net_count = observable.len();
+  // For each net: the FETs driving it, and whether anything other than one FET's source uses it.
+  let mut fet_drivers: Vec<Vec<usize>> = vec![Vec::new(); net_count];
+  let mut other_drivers: Vec<bool> = vec![false; net_count];
+  let mut source_uses: Vec<u32> = vec![0; net_count];
+  let mut other_uses: Vec<bool> = observable.to_vec();
+  for (index, component) in components.iter().enumerate() {
+    match component {
+      Component::Fet{ gate, drain, source, .. } => {
+        fet_drivers[*drain as usize].push(index);
+        source_uses[*source as usize] += 1;
+        other_uses[*gate as usize] = true;
+      }
+      _ => for net in component.nets() {
+        other_drivers[net as usize] = true;
+        other_uses[net as usize] = true;
+      },
+    }
+  }
+  let fet = |index: usize| match components[index] {
+    Component::Fet{ is_pfet, gate, drain, source, .. } => (is_pfet, gate, drain, source),
+    _ => unreachable!(),
+  };
+  let strength = |index: usize| match components[index] {
+    Component::Fet{ size, .. } => size.strength(),
+    _ => unreachable!(),
+  };
+  // A net we may hide inside a cell: driven by exactly one FET and used by nothing but one other FET's source.
+  let is_private = |net: Net| {
+    let net = net as usize;
+    fet_drivers[net].len() == 1 && !other_drivers[net] && !other_uses[net] && source_uses[net] == 1
+  };
+
+  let shape_of = |fets: &[usize]| -> Option<Shape> {
+    match fets.len() {
+      0 => None,
+      1 => {
+        // Walk down through private nets to find a series chain.
+        let mut chain = vec![fets[0]];
+        let (is_pfet, _, _, mut source) = fet(fets[0]);
+        while is_private(source) {
+          let below = fet_drivers[source as usize][0];
+          let (below_is_pfet, _, _, below_source) = fet(below);
+          if below_is_pfet != is_pfet || chain.contains(&below) || chain.len() == 32 {
+            break;
+          }
+          chain.push(below);
+          source = below_source;
+        }
+        Some(Shape::Series { source, fets: chain })
+      }
+      _ => {
+        let source = fet(fets[0]).3;
+        match fets.iter().all(|&f| fet(f).3 == source) {
+          true => Some(Shape::Parallel { source, fets: fets.to_vec() }),
+          false => None,
+        }
+      }
+    }
+  };
+
+  let mut cells: Vec<CmosCell> = Vec::new();
+  let mut absorbed: Vec<bool> = vec![false; components.len()];
+  for output in 0..net_count {
+    // If anything other than FETs drives the output then merge order could matter, so leave it alone.
+    if other_drivers[output] || fet_drivers[output].is_empty() {
+      continue;
+    }
+    let (pfets, nfets): (Vec<usize>, Vec<usize>) = fet_drivers[output].iter().partition(|&&f| fet(f).0);
+    let (pull_up, pull_down) = match (shape_of(&pfets), shape_of(&nfets)) {
+      (Some(pull_up), Some(pull_down)) => (pull_up, pull_down),
+      _ => continue,
+    };
+    // A cell can't tell which FETs would win a fight, so its FETs must all be the same size.
+    let mut cell_fets = pull_up.fets().iter().chain(pull_down.fets());
+    let first_strength = strength(*cell_fets.next().unwrap());
+    if cell_fets.any(|&f| strength(f) != first_strength) {
+      continue;
+    }
+    // The two networks must be duals over the same inputs: inverter, NAND (parallel up, series down) or NOR.
+    let dual = match (&pull_up, &pull_down) {
+      (Shape::Series { fets: up, .. }, Shape::Series { fets: down, .. }) => up.len() == 1 && down.len() == 1,
+      (Shape::Parallel { .. }, Shape::Series { .. }) | (Shape::Series { .. }, Shape::Parallel { .. }) => true,
+      (Shape::Parallel { .. }, Shape::Parallel { .. }) => false,
+    };
+    let mut up_gates: Vec<Net> = pull_up.fets().iter().map(|&f| fet(f).1).collect();
+    let mut down_gates: Vec<Net> = pull_down.fets().iter().map(|&f| fet(f).1).collect();
+    up_gates.sort_unstable();
+    down_gates.sort_unstable();
+    if !dual || up_gates != down_gates {
+      continue;
+    }
+    for &f in pull_up.fets().iter().chain(pull_down.fets()) {
+      absorbed[f] = true;
+    }
+    cells.push(CmosCell {
+      output: output as Net,
+      pull_up: pull_up.into_network(true, components),
+      pull_down: pull_down.into_network(false, components),
+    });
+  }
+
+  let mut index = 0;
+  components.retain(|_| {
+    index += 1;
+    !absorbed[index - 1]
+  });
+  components.extend(cells.into_iter().map(Component::Cell));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::testing::{cell, fet, rails, seeded_below, signal, GND, SENTINEL, VDD};
+  use super::super::Simulator;
+
+  // Simulates with and without abstraction and checks nothing can tell the difference, both tracing every net and
+  // tracing only visible_nets, which lets abstraction fold away the internal nodes of series chains.
+  fn assert_abstraction_invisible(description: &[u32], net_count: u32, visible_nets: &[u32]) {
+    let all_nets: Vec<u32> = (0..net_count).collect();
+    for nets in [&all_nets[..], visible_nets] {
+      let run = |enabled: bool| {
+        let results = Simulator::new(description, net_count).with_gate_abstraction(enabled).run(nets, 80, 3);
+        (results.traces, results.first_shoot_through)
+      };
+      assert_eq!(run(false), run(true));
+    }
+  }
+
+  #[test]
+  fn textbook_cells() {
+    // An inverter driving a NAND2 driving a NOR3, with a pull-down fighting the NOR's output.
+    let mut description = rails();
+    description.extend(signal(2, &[0, 1], true));
+    description.extend(signal(3, &[0, 0, 1, 1], true));
+    description.extend(signal(4, &[1, 0, 2], true));
+    description.extend([fet(true, 2, 5, VDD), fet(false, 2, 5, GND)].concat());
+    description.extend([fet(true, 5, 6, VDD), fet(true, 3, 6, VDD), fet(false, 5, 6, 7), fet(false, 3, 7, GND)].concat());
+    description.extend([fet(true, 6, 8, VDD), fet(true, 3, 9, 8), fet(true, 4, 10, 9)].concat());
+    description.extend([fet(false, 6, 10, GND), fet(false, 3, 10, GND), fet(false, 4, 10, GND)].concat());
+    description.extend([3, 1, 10, SENTINEL]);
+    assert_abstraction_invisible(&description, 11, &[2, 3, 4, 5, 6, 10]);
+  }
+
+  #[test]
+  fn shoot_through() {
+    // An inverter whose output is also driven by a signal, so it shoots through as soon as they disagree.
+    let mut description = rails();
+    description.extend(signal(2, &[0, 0, 1], true));
+    description.extend([fet(true, 2, 3, VDD), fet(false, 2, 3, GND), fet(true, 3, 4, VDD), fet(false, 3, 4, GND)].concat());
+    description.extend(signal(3, &[2, 2, 2, 1], false));
+    assert_abstraction_invisible(&description, 5, &[2, 4]);
+  }
+
+  #[test]
+  fn random_netlists() {
+    let mut below = seeded_below();
+    for _ in 0..200 {
+      let mut description = rails();
+      let mut next = 2;
+      let mut drivers: Vec<u32> = vec![VDD, GND];
+      for _ in 0..2 + below(4) {
+        let pattern: Vec<u32> = (0..1 + below(6)).map(|_| below(4).min(2)).collect();
+        description.extend(signal(next, &pattern, below(2) == 0));
+        drivers.push(next);
+        next += 1;
+      }
+      // Inverters, NANDs and NORs over earlier nets, sometimes sharing an output or with a pull resistor on it.
+      let cell_count = 3 + below(10);
+      let outputs: Vec<u32> = (next .. next + cell_count).collect();
+      next += cell_count;
+      drivers.extend(&outputs);
+      for index in 0..cell_count as usize {
+        let output = match below(10) {
+          0 => outputs[below(cell_count) as usize],
+          _ => outputs[index],
+        };
+        let kind = below(3);
+        let inputs: Vec<u32> = (0..[1, 2, 3][kind as usize]).map(|_| drivers[below(drivers.len() as u32) as usize]).collect();
+        description.extend(cell(kind, &inputs, output, &mut next));
+        if below(5) == 0 {
+          description.extend([3, below(2), output, SENTINEL]);
+        }
+      }
+      // A few stray FETs anywhere.
+      for _ in 0..below(4) {
+        description.extend(fet(below(2) == 0, below(next), below(next), below(next)));
+      }
+      assert_abstraction_invisible(&description, next, &drivers);
+    }
+  }
+}
@@ -0,0 +1,404 @@
+
+use super::{fet_contention, level_drive, merge_drives, resistive_drive, spread_resistor_pulls, netlist::Netlist, Component, DriveType, Fault, Net, NetState, Simulator};
+
+// How many input assignments the search may try for one fault before giving up on it.
+const SEARCH_LIMIT: u32 = 1 << 14;
+
+// What a net settles to. Unassigned means it depends on inputs the search hasn't picked yet.
+#[derive(Clone, Copy, PartialEq)]
+enum Value {
+  Low,
+  High,
+  Floating,
+  ShootThrough,
+  Unassigned,
+}
+
+impl Value {
+  fn is_level(self) -> bool {
+    matches!(self, Value::Low | Value::High)
+  }
+
+  fn net_state(self) -> NetState {
+    match self {
+      Value::Low => NetState::Low,
+      Value::High => NetState::High,
+      Value::ShootThrough => NetState::ShootThrough,
+      Value::Floating | Value::Unassigned => NetState::Invalid,
+    }
+  }
+}
+
+// A combinational netlist, evaluated straight to the values its nets settle to for some inputs.
+struct Model {
+  components: Vec<Component>,
+  net_count: usize,
+  inputs: Vec<Net>,
+  outputs: Vec<Net>,
+  // For each net, the nets whose values can change what it settles to.
+  fan_in: Vec<Vec<Net>>,
+  resistors: Vec<(Net, Net)>,
+}
+
+enum Outcome {
+  Detected,
+  // Some outputs still depend on unassigned inputs, and could yet show the fault.
+  Undecided,
+  Undetectable,
+}
+
+impl Model {
+  fn new(netlist: Netlist, inputs: Vec<Net>, outputs: Vec<Net>) -> Model {
+    let net_count = netlist.net_count() as usize;
+    let mut fan_in: Vec<Vec<Net>> = vec![Vec::new(); net_count];
+    let mut resistors: Vec<(Net, Net)> = Vec::new();
+    for component in &netlist.components {
+      match component {
+        Component::Fet{ gate, drain, source, .. } => fan_in[*drain as usize].extend([*gate, *source]),
+        Component::Gate(gate) => fan_in[gate.output as usize].extend(gate.inputs()),
+        Component::Resistor{ net_a, net_b } => {
+          fan_in[*net_a as usize].push(*net_b);
+          fan_in[*net_b as usize].push(*net_a);
+          resistors.push((*net_a, *net_b));
+        }
+        Component::Signal{ .. } | Component::PullResistor{ .. } | Component::Capacitor{ .. } => (),
+        _ => panic!("Test generation only handles FETs, gates, resistors, capacitors and signals"),
+      }
+    }
+    Model { components: netlist.components, net_count, inputs, outputs, fan_in, resistors }
+  }
+
+  // Settles the netlist with the faulty circuit's behavior, returning the value of every output.
+  // Unassigned inputs are pessimistic: anything they might switch on drives Unknown, which merges to Unassigned.
+  fn evaluate(&self, assignment: &[Option<bool>], fault: Option<Fault>) -> Vec<Value> {
+    let mut values = vec![Value::Floating; self.net_count];
+    let mut drives = vec![DriveType::HighZ; self.net_count];
+    let mut changed: Vec<bool> = vec![true; self.net_count];
+    // A combinational netlist settles within one pass per net, so anything still changing after that is oscillating.
+    for _ in 0..=self.net_count {
+      drives.fill(DriveType::HighZ);
+      for (net, input) in self.inputs.iter().zip(assignment) {
+        drives[*net as usize] = input.map_or(DriveType::Unknown, level_drive);
+      }
+      let net_states: Vec<NetState> = values.iter().map(|value| value.net_state()).collect();
+      // Strength of the strongest FET definitely pulling each net (low, high), resolved once every FET has been seen.
+      let mut fet_strengths: Vec<(f64, f64)> = vec![(0.0, 0.0); self.net_count];
+      let mut fet_index: u32 = 0;
+      for component in &self.components {
+        let (net, drive) = match component {
+          Component::Fet{ is_pfet, gate, drain, source, size } => {
+            let (on_level, rail) = match is_pfet {
+              false => (Value::High, Value::Low),
+              true => (Value::Low, Value::High),
+            };
+            let possibly = |value: Value, wanted: Value| match value {
+              Value::Unassigned => None,
+              value => Some(value == wanted),
+            };
+            let conducting = match fault {
+              Some(Fault::FetStuck { fet, closed, .. }) if fet == fet_index => Some(closed),
+              _ => possibly(values[*gate as usize], on_level),
+            };
+            fet_index += 1;
+            match (conducting, possibly(values[*source as usize], rail)) {
+              (Some(false), _) | (_, Some(false)) => continue,
+              (Some(true), Some(true)) => {
+                let strengths = &mut fet_strengths[*drain as usize];
+                match is_pfet {
+                  false => strengths.0 = strengths.0.max(size.strength()),
+                  true => strengths.1 = strengths.1.max(size.strength()),
+                }
+                continue;
+              }
+              _ => (*drain, DriveType::Unknown),
+            }
+          }
+          Component::Gate(gate) => (gate.output, gate.settled_drive(&net_states)),
+          Component::PullResistor{ is_pull_down, net } => (*net, match is_pull_down {
+            true => DriveType::WeakLow,
+            false => DriveType::WeakHigh,
+          }),
+          // Signals on the inputs are replaced by the assignment, and any others hold their first value.
+          Component::Signal{ net, .. } if self.inputs.contains(net) => continue,
+          Component::Signal{ pattern, net, .. } => (*net, pattern[0]),
+          _ => continue,
+        };
+        drives[net as usize] = merge_drives(drives[net as usize], drive);
+      }
+      for (net, &(low_strength, high_strength)) in fet_strengths.iter().enumerate() {
+        drives[net] = merge_drives(drives[net], fet_contention(low_strength, high_strength));
+      }
+      // Whether resistors pull each net (low, high), and whether one might but we don't know which way.
+      let mut resistor_pulls: Vec<(bool, bool, bool)> = vec![(false, false, false); self.net_count];
+      spread_resistor_pulls(&self.resistors, &mut resistor_pulls, |net, pulls| match (drives[net], values[net]) {
+        (DriveType::HighZ, _) => pulls,
+        (_, Value::Low) => (true, false, false),
+        (_, Value::High) => (false, true, false),
+        (_, Value::Unassigned) => (false, false, true),
+        _ => (false, false, false),
+      }, |a, b| (a.0 || b.0, a.1 || b.1, a.2 || b.2));
+      for (net, &(pulled_low, pulled_high, pulled_unknown)) in resistor_pulls.iter().enumerate() {
+        if let DriveType::HighZ = drives[net] {
+          drives[net] = match pulled_unknown {
+            true => DriveType::Unknown,
+            false => resistive_drive((pulled_low, pulled_high)),
+          };
+        }
+      }
+
+      let mut any_changed = false;
+      for net in 0..self.net_count {
+        let mut value = match drives[net] {
+          DriveType::Low | DriveType::WeakLow => Value::Low,
+          DriveType::High | DriveType::WeakHigh => Value::High,
+          DriveType::HighZ => Value::Floating,
+          DriveType::ShootThrough => Value::ShootThrough,
+          DriveType::Unknown => Value::Unassigned,
+        };
+        if let Some(Fault::StuckAt { net: stuck, level, .. }) = fault {
+          if stuck as usize == net {
+            value = level_value(level);
+          }
+        }
+        changed[net] = value != values[net];
+        any_changed |= changed[net];
+        values[net] = value;
+      }
+      if !any_changed {
+        break;
+      }
+    }
+    self.outputs.iter().map(|&net| match changed[net as usize] {
+      true => Value::Unassigned,
+      false => values[net as usize],
+    }).collect()
+  }
+
+  // Whether the faulty outputs show the fault, judged like simulate_faults: a graded output differs from the good
+  // circuit where the good circuit has it Low or High.
+  fn compare(good: &[Value], faulty: &[Value]) -> Outcome {
+    let mut undecided = false;
+    for (&good, &faulty) in good.iter().zip(faulty) {
+      match (good, faulty) {
+        (Value::Unassigned, _) => undecided = true,
+        (good, _) if !good.is_level() => (),
+        (_, Value::Unassigned) => undecided = true,
+        (good, faulty) if good != faulty => return Outcome::Detected,
+        _ => (),
+      }
+    }
+    match undecided {
+      true => Outcome::Undecided,
+      false => Outcome::Undetectable,
+    }
+  }
+
+  fn detects(&self, vector: &[bool], fault: Fault) -> bool {
+    let assignment: Vec<Option<bool>> = vector.iter().map(|&level| Some(level)).collect();
+    let good = self.evaluate(&assignment, None);
+    matches!(Model::compare(&good, &self.evaluate(&assignment, Some(fault))), Outcome::Detected)
+  }
+
+  // The net where a fault first shows up.
+  fn fault_site(&self, fault: Fault) -> Net {
+    match fault {
+      Fault::StuckAt { net, .. } | Fault::Bridge { net_a: net, .. } => net,
+      Fault::FetStuck { fet, .. } => self.components.iter().filter_map(|component| match component {
+        Component::Fet{ drain, .. } => Some(*drain),
+        _ => None,
+      }).nth(fet as usize).unwrap(),
+    }
+  }
+
+  // PODEM-style search: assign inputs one at a time, settling the good and faulty circuits with the rest unassigned
+  // after each, and backtrack as soon as no output could show the fault. Inputs that feed the fault site are tried
+  // first, since they're the ones that can activate it. Returns Err(true) if the fault is undetectable,
+  // or Err(false) if the search gave up.
+  fn find_test(&self, fault: Fault) -> Result<Vec<bool>, bool> {
+    let mut in_cone = vec![false; self.net_count];
+    let mut stack = vec![self.fault_site(fault)];
+    while let Some(net) = stack.pop() {
+      if !in_cone[net as usize] {
+        in_cone[net as usize] = true;
+        stack.extend(&self.fan_in[net as usize]);
+      }
+    }
+    let mut order: Vec<usize> = (0..self.inputs.len()).collect();
+    order.sort_by_key(|&i| !in_cone[self.inputs[i] as usize]);
+
+    let mut assignment: Vec<Option<bool>> = vec![None; self.inputs.len()];
+    let mut tries: u32 = 0;
+    match self.search(fault, &order, &mut assignment, &mut tries) {
+      Some(true) => Ok(filled(&assignment)),
+      Some(false) => Err(true),
+      None => Err(false),
+    }
+  }
+
+  // Returns whether the fault was detected with assignment as it's left, or None if we ran out of tries.
+  fn search(&self, fault: Fault, order: &[usize], assignment: &mut [Option<bool>], tries: &mut u32) -> Option<bool> {
+    *tries += 1;
+    if *tries > SEARCH_LIMIT {
+      return None;
+    }
+    let good = self.evaluate(assignment, None);
+    match Model::compare(&good, &self.evaluate(assignment, Some(fault))) {
+      // Unassigned inputs are filled with Low in the final vector, so make sure that still works.
+      Outcome::Detected if self.detects(&filled(assignment), fault) => return Some(true),
+      Outcome::Detected => (),
+      Outcome::Undetectable => return Some(false),
+      Outcome::Undecided => (),
+    }
+    let next = match order.iter().find(|&&i| assignment[i].is_none()) {
+      Some(&next) => next,
+      None => return Some(false),
+    };
+    for level in [false, true] {
+      assignment[next] = Some(level);
+      if self.search(fault, order, assignment, tries)? {
+        return Some(true);
+      }
+    }
+    assignment[next] = None;
+    Some(false)
+  }
+}
+
+fn filled(assignment: &[Option<bool>]) -> Vec<bool> {
+  assignment.iter().map(|input| input.unwrap_or(false)).collect()
+}
+
+fn level_value(level: bool) -> Value {
+  match level {
+    false => Value::Low,
+    true => Value::High,
+  }
+}
+
+/// Test vectors for a combinational netlist from generate_tests.
+pub struct TestSet {
+  pub inputs: Vec<Net>,
+  /// Each vector holds one level per input, and should be held until the outputs settle.
+  pub vectors: Vec<Vec<bool>>,
+  pub fault_count: u32,
+  /// Faults no input vector can detect on the given outputs.
+  pub redundant: Vec<Fault>,
+  /// Faults the search gave up on.
+  pub aborted: Vec<Fault>,
+}
+
+impl TestSet {
+  /// One Signal pattern per input, of pattern vars 0 and 1, applying the vectors in order.
+  pub fn patterns(&self) -> Vec<Vec<u32>> {
+    (0..self.inputs.len()).map(|i| self.vectors.iter().map(|vector| vector[i] as u32).collect()).collect()
+  }
+}
+
+/// Generates a compact set of test vectors for every single stuck fault in a combinational netlist (see
+/// Simulator::single_stuck_faults), applied on input_nets and observed on output_nets. Signals on other nets hold
+/// their first value, so rails work as usual. Every vector is fault simulated against the faults still undetected,
+/// and vectors that only detect faults later ones also detect are dropped at the end.
+pub fn generate_tests(description: &[u32], net_count: u32, input_nets: &[Net], output_nets: &[Net]) -> TestSet {
+  let faults = Simulator::new(description, net_count).single_stuck_faults();
+  let netlist = Netlist::parse(description, net_count);
+  let canonical = |nets: &[Net]| nets.iter().map(|&net| netlist.canonical[net as usize]).collect();
+  let (inputs, outputs) = (canonical(input_nets), canonical(output_nets));
+  let model = Model::new(netlist, inputs, outputs);
+
+  let mut vectors: Vec<Vec<bool>> = Vec::new();
+  let mut detected: Vec<Fault> = Vec::new();
+  let mut redundant: Vec<Fault> = Vec::new();
+  let mut aborted: Vec<Fault> = Vec::new();
+  for &fault in &faults {
+    if detected.contains(&fault) {
+      continue;
+    }
+    match model.find_test(fault) {
+      Ok(vector) => {
+        let newly_detected: Vec<Fault> = faults.iter().copied().filter(|other| {
+          !detected.contains(other) && !redundant.contains(other) && !aborted.contains(other) && model.detects(&vector, *other)
+        }).collect();
+        detected.extend(newly_detected);
+        vectors.push(vector);
+      }
+      Err(true) => redundant.push(fault),
+      Err(false) => aborted.push(fault),
+    }
+  }
+
+  // Reverse order compaction: later vectors tend to catch the hard faults, and usually the easy ones along the way.
+  let mut kept: Vec<Vec<bool>> = Vec::new();
+  for vector in vectors.into_iter().rev() {
+    let count = detected.len();
+    detected.retain(|&fault| !model.detects(&vector, fault));
+    if detected.len() < count {
+      kept.push(vector);
+    }
+  }
+  kept.reverse();
+  TestSet { inputs: input_nets.to_vec(), vectors: kept, fault_count: faults.len() as u32, redundant, aborted }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::testing::{cell, rails, seeded_below, signal};
+  use super::super::{simulate_faults, Net, Simulator};
+  use super::{generate_tests, Fault};
+
+  // Ticks each vector is held for, which is plenty for these netlists to settle.
+  const HOLD_TICKS: u32 = 40;
+
+  #[test]
+  fn random_netlists() {
+    let mut below = seeded_below();
+    for _ in 0..60 {
+      // A few inputs, then inverters, NANDs and NORs over the inputs and earlier cells' outputs. Nothing weaker than
+      // a FET, since a pull resistor can hold a net at the wrong level for a tick at power-up.
+      let input_nets: Vec<Net> = (2..4 + below(3)).collect();
+      let mut next = *input_nets.last().unwrap() + 1;
+      let mut drivers = input_nets.clone();
+      let mut description = rails();
+      for _ in 0..2 + below(6) {
+        let kind = below(3);
+        let inputs: Vec<Net> = (0..[1, 2, 3][kind as usize]).map(|_| drivers[below(drivers.len() as u32) as usize]).collect();
+        let output = next;
+        next += 1;
+        description.extend(cell(kind, &inputs, output, &mut next));
+        drivers.push(output);
+      }
+      let output_nets: Vec<Net> = drivers[input_nets.len()..].to_vec();
+      let test_set = generate_tests(&description, next, &input_nets, &output_nets);
+      let faults = Simulator::new(&description, next).single_stuck_faults();
+      assert_eq!(test_set.fault_count as usize, faults.len());
+      // A few inputs are nowhere near enough for the search to run out of tries.
+      assert!(test_set.aborted.is_empty());
+
+      // Applies each vector on its own, from power-up, and returns the faults none of them detect. Back to back, a
+      // node left over from one vector can fight the next one's drive for a tick and latch a shoot through, which is
+      // a timing hazard the combinational model rightly knows nothing about.
+      let undetected = |patterns: &[Vec<u32>], faults: &[Fault]| {
+        let mut undetected = faults.to_vec();
+        for vector in 0..patterns[0].len() {
+          let mut stimulus = description.clone();
+          for (&net, pattern) in input_nets.iter().zip(patterns) {
+            stimulus.extend(signal(net, &[pattern[vector]], false));
+          }
+          let make_simulator = || Simulator::new(&stimulus, next);
+          undetected = simulate_faults(&make_simulator, &undetected, &output_nets, &[HOLD_TICKS], HOLD_TICKS, HOLD_TICKS).undetected;
+        }
+        undetected
+      };
+      // The vectors detect every fault but the redundant ones.
+      let untested: Vec<Fault> = faults.iter().copied().filter(|fault| test_set.redundant.contains(fault)).collect();
+      match test_set.vectors.is_empty() {
+        true => assert_eq!(untested, faults),
+        false => assert_eq!(undetected(&test_set.patterns(), &faults), untested),
+      }
+
+      // Not even every input vector detects a redundant fault.
+      let vector_count = 1 << input_nets.len();
+      let exhaustive: Vec<Vec<u32>> = (0..input_nets.len()).map(|i| (0..vector_count).map(|v| v >> i & 1).collect()).collect();
+      assert_eq!(undetected(&exhaustive, &test_set.redundant), test_set.redundant);
+    }
+  }
+}
@@ -0,0 +1,130 @@
+
+use wasm_bindgen::prelude::*;
+use super::{merge_drives, parse_pattern_var, DriveType, Net, NetState, SimulationResults, WarningKind};
+
+/// A behavioral component written in Rust (or, through add_device, in JS) and plugged into a simulation with
+/// Simulator::add_device. A device never sees the netlist: each tick it gets the states of its declared inputs
+/// and fills in drives for its declared outputs, keeping whatever internal state it likes in between.
+pub trait Device {
+  /// Nets this device reads, in the order DeviceTick::inputs holds their states.
+  fn inputs(&self) -> Vec<Net>;
+  /// Nets this device drives, in the order DeviceTick::outputs holds their drives.
+  fn outputs(&self) -> Vec<Net>;
+  /// Called once per tick, after the tick's net states are known and before they're updated from the drives.
+  fn step(&mut self, tick: &mut DeviceTick);
+  /// Called once at the end of the simulation, to add anything the device collected to the results.
+  fn finish(&mut self, _results: &mut SimulationResults) {}
+}
+
+/// What a device sees and does on one tick.
+pub struct DeviceTick<'a> {
+  pub t: u32,
+  pub inputs: &'a [NetState],
+  /// Every output starts each tick at HighZ.
+  pub outputs: &'a mut [DriveType],
+  index: u32,
+  warnings: &'a mut Vec<u32>,
+}
+
+impl<'a> DeviceTick<'a> {
+  /// Reports a DeviceWarning for this device, with a detail word of the device's choosing.
+  pub fn warn(&mut self, detail: u32) {
+    self.warn_kind(WarningKind::DeviceWarning, detail);
+  }
+
+  pub(crate) fn warn_kind(&mut self, kind: WarningKind, detail: u32) {
+    self.warnings.extend_from_slice(&[self.t, kind as u32, self.index, detail]);
+  }
+}
+
+// A device in the simulation, with its nets renamed like any other component's and buffers for each tick.
+pub struct PluggedDevice {
+  device: Box<dyn Device>,
+  inputs: Vec<Net>,
+  outputs: Vec<Net>,
+  input_states: Vec<NetState>,
+  output_drives: Vec<DriveType>,
+}
+
+impl PluggedDevice {
+  pub fn new(device: Box<dyn Device>) -> PluggedDevice {
+    let inputs = device.inputs();
+    let outputs = device.outputs();
+    let input_states = vec![NetState::Invalid; inputs.len()];
+    let output_drives = vec![DriveType::HighZ; outputs.len()];
+    PluggedDevice { device, inputs, outputs, input_states, output_drives }
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    [&self.inputs[..], &self.outputs[..]].concat()
+  }
+
+  pub fn inputs(&self) -> &[Net] {
+    &self.inputs
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    for net in self.inputs.iter_mut().chain(self.outputs.iter_mut()) {
+      *net = f(*net);
+    }
+  }
+
+  // index is this device's position among devices, for warnings.
+  pub fn step(&mut self, t: u32, net_states: &[NetState], drives: &mut [DriveType], index: u32, warnings: &mut Vec<u32>) {
+    for (state, net) in self.input_states.iter_mut().zip(&self.inputs) {
+      *state = net_states[*net as usize];
+    }
+    for drive in self.output_drives.iter_mut() {
+      *drive = DriveType::HighZ;
+    }
+    self.device.step(&mut DeviceTick { t, inputs: &self.input_states, outputs: &mut self.output_drives, index, warnings });
+    for (net, drive) in self.outputs.iter().zip(&self.output_drives) {
+      drives[*net as usize] = merge_drives(drives[*net as usize], *drive);
+    }
+  }
+
+  pub fn finish(&mut self, results: &mut SimulationResults) {
+    self.device.finish(results);
+  }
+}
+
+#[wasm_bindgen]
+extern "C" {
+  #[derive(Clone)]
+  pub type JsDevice;
+
+  #[wasm_bindgen(method)]
+  fn step(this: &JsDevice, t: u32, inputs: &[u8]) -> Vec<u8>;
+}
+
+// A device implemented in JS, registered with add_device.
+#[derive(Clone)]
+pub struct ScriptedDevice {
+  inputs: Vec<Net>,
+  outputs: Vec<Net>,
+  object: JsDevice,
+}
+
+impl ScriptedDevice {
+  pub fn new(inputs: Vec<Net>, outputs: Vec<Net>, object: JsDevice) -> ScriptedDevice {
+    ScriptedDevice { inputs, outputs, object }
+  }
+}
+
+impl Device for ScriptedDevice {
+  fn inputs(&self) -> Vec<Net> {
+    self.inputs.clone()
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    self.outputs.clone()
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    let inputs: Vec<u8> = tick.inputs.iter().map(|state| *state as u8).collect();
+    let drives = self.object.step(tick.t, &inputs);
+    for (output, drive) in tick.outputs.iter_mut().zip(drives) {
+      *output = parse_pattern_var(&(drive as u32));
+    }
+  }
+}
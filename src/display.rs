@@ -0,0 +1,165 @@
+
+use super::{pack_word, Device, DeviceTick, Net, SimulationResults};
+
+#[derive(Clone, Copy)]
+pub enum DisplayMode {
+  // Any nonzero value is a white pixel.
+  Mono,
+  // Eight bits per pixel: red in bits 5-7, green in bits 2-4, blue in bits 0-1.
+  Rgb332,
+  // Red in bits 16-23, green in bits 8-15, blue in bits 0-7.
+  Rgb888,
+  // Each word is one digit: segments a to g in bits 0 to 6, and the decimal point in bit 7.
+  SevenSegment,
+}
+
+impl DisplayMode {
+  pub fn from_opcode(mode: u32) -> DisplayMode {
+    match mode {
+      0 => DisplayMode::Mono,
+      1 => DisplayMode::Rgb332,
+      2 => DisplayMode::Rgb888,
+      3 => DisplayMode::SevenSegment,
+      _ => panic!("Invalid display mode: {}", mode),
+    }
+  }
+}
+
+// Each seven-segment digit is drawn in a cell this big, with segments as (first column, first row, width, height).
+const DIGIT_WIDTH: u32 = 8;
+const DIGIT_HEIGHT: u32 = 11;
+const SEGMENTS: [(u32, u32, u32, u32); 8] = [
+  (1, 0, 5, 1),
+  (6, 1, 1, 4),
+  (6, 6, 1, 4),
+  (1, 10, 5, 1),
+  (0, 6, 1, 4),
+  (0, 1, 1, 4),
+  (1, 5, 5, 1),
+  (7, 10, 1, 1),
+];
+
+// Pixels (or segments) written with Invalid bits are shown in this color.
+const INVALID_COLOR: [u8; 3] = [255, 0, 255];
+
+/// Everything written to one display during a simulation, from which its image at any tick can be rebuilt.
+pub struct Framebuffer {
+  mode: DisplayMode,
+  // In pixels, or in digits for seven-segment displays.
+  width: u32,
+  height: u32,
+  // (tick, pixel index, value, invalid mask) for every write that changed a pixel, in order.
+  writes: Vec<(u32, u32, u32, u32)>,
+}
+
+impl Framebuffer {
+  /// The size of the image in pixels.
+  pub fn image_size(&self) -> (u32, u32) {
+    match self.mode {
+      DisplayMode::SevenSegment => (self.width * DIGIT_WIDTH, self.height * DIGIT_HEIGHT),
+      _ => (self.width, self.height),
+    }
+  }
+
+  /// The image as it was shown at the start of tick t, as RGBA bytes row by row. Unwritten pixels are black.
+  pub fn rgba_at(&self, t: u32) -> Vec<u8> {
+    let mut words = vec![(0, 0); (self.width * self.height) as usize];
+    for &(_, pixel, value, invalid_mask) in self.writes.iter().take_while(|write| write.0 < t) {
+      words[pixel as usize] = (value, invalid_mask);
+    }
+    let (image_width, image_height) = self.image_size();
+    let mut rgba = vec![0; (image_width * image_height * 4) as usize];
+    let mut put = |x: u32, y: u32, color: [u8; 3]| {
+      let offset = ((y * image_width + x) * 4) as usize;
+      rgba[offset .. offset + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+    };
+    for (pixel, &(value, invalid_mask)) in words.iter().enumerate() {
+      let (x, y) = (pixel as u32 % self.width, pixel as u32 / self.width);
+      let color = match self.mode {
+        DisplayMode::SevenSegment => {
+          for (segment, &(column, row, width, height)) in SEGMENTS.iter().enumerate() {
+            let color = match (invalid_mask >> segment & 1, value >> segment & 1) {
+              (1, _) => INVALID_COLOR,
+              (_, 1) => [255, 32, 0],
+              _ => [48, 8, 0],
+            };
+            for dy in 0..height {
+              for dx in 0..width {
+                put(x * DIGIT_WIDTH + column + dx, y * DIGIT_HEIGHT + row + dy, color);
+              }
+            }
+          }
+          continue;
+        }
+        _ if invalid_mask != 0 => INVALID_COLOR,
+        DisplayMode::Mono => [if value != 0 { 255 } else { 0 }; 3],
+        DisplayMode::Rgb332 => [
+          ((value >> 5 & 7) * 255 / 7) as u8,
+          ((value >> 2 & 7) * 255 / 7) as u8,
+          ((value & 3) * 255 / 3) as u8,
+        ],
+        DisplayMode::Rgb888 => [(value >> 16) as u8, (value >> 8) as u8, value as u8],
+      };
+      put(x, y, color);
+    }
+    rgba
+  }
+}
+
+// A memory-mapped display. Pixel (or digit) i, counting across rows, is at base_address + i, and like an SRAM it's
+// written on every tick that write is High with its address on the address nets.
+pub struct Display {
+  base_address: u32,
+  address_nets: Vec<Net>,
+  data_nets: Vec<Net>,
+  write: Net,
+  // The current (value, invalid mask) of each pixel, to only log writes that change something.
+  pixels: Vec<(u32, u32)>,
+  framebuffer: Option<Framebuffer>,
+}
+
+impl Display {
+  pub fn new(mode: DisplayMode, width: u32, height: u32, base_address: u32, address_nets: Vec<Net>, data_nets: Vec<Net>, write: Net) -> Display {
+    Display {
+      base_address,
+      address_nets,
+      data_nets,
+      write,
+      pixels: vec![(0, 0); (width * height) as usize],
+      framebuffer: Some(Framebuffer { mode, width, height, writes: Vec::new() }),
+    }
+  }
+}
+
+impl Device for Display {
+  fn inputs(&self) -> Vec<Net> {
+    [&[self.write], &self.address_nets[..], &self.data_nets[..]].concat()
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    Vec::new()
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    if tick.inputs[0].level() != Some(true) {
+      return;
+    }
+    let (address_states, data_states) = tick.inputs[1..].split_at(self.address_nets.len());
+    let pixel = match pack_word(address_states.iter()) {
+      (address, 0) => address.wrapping_sub(self.base_address),
+      _ => return,
+    };
+    if pixel as usize >= self.pixels.len() {
+      return;
+    }
+    let word = pack_word(data_states.iter());
+    if self.pixels[pixel as usize] != word {
+      self.pixels[pixel as usize] = word;
+      self.framebuffer.as_mut().unwrap().writes.push((tick.t, pixel, word.0, word.1));
+    }
+  }
+
+  fn finish(&mut self, results: &mut SimulationResults) {
+    results.framebuffers.extend(self.framebuffer.take());
+  }
+}
@@ -0,0 +1,463 @@
+
+use super::{edge_signal_output, settle_ticks, signal_output, spread_resistor_pulls, Component, DriveType, FetSize, Net, NetState, Simulator};
+
+/// A fault injected into a simulation with Simulator::inject_fault or set_faults, active from tick from onwards.
+/// Nets are numbered as in the description, and FETs by their position among the description's FETs.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fault {
+  /// The net reads as this level no matter what drives it.
+  StuckAt { net: Net, level: bool, from: u32 },
+  /// The FET never conducts, or always conducts (whenever its source is at its rail), whatever its gate does.
+  FetStuck { fet: u32, closed: bool, from: u32 },
+  /// The two nets are shorted together, so each sees both of their drives.
+  Bridge { net_a: Net, net_b: Net, from: u32 },
+}
+
+impl Fault {
+  /// Unpacks a fault list: four words per fault, (kind, target, other, from), where kind is 0 stuck-at-0 on net
+  /// target, 1 stuck-at-1 on net target, 2 FET number target stuck open, 3 FET number target stuck closed,
+  /// or 4 a bridge between nets target and other. other is ignored except for bridges.
+  pub fn parse_list(faults: &[u32]) -> Vec<Fault> {
+    faults.chunks(4).map(|record| {
+      let (target, other, from) = (record[1], record[2], record[3]);
+      match record[0] {
+        0 | 1 => Fault::StuckAt { net: target, level: record[0] == 1, from },
+        2 | 3 => Fault::FetStuck { fet: target, closed: record[0] == 3, from },
+        4 => Fault::Bridge { net_a: target, net_b: other, from },
+        kind => panic!("Invalid fault kind: {}", kind),
+      }
+    }).collect()
+  }
+
+  /// Packs this fault into the four words parse_list reads.
+  pub fn to_words(&self) -> [u32; 4] {
+    match *self {
+      Fault::StuckAt { net, level, from } => [level as u32, net, 0, from],
+      Fault::FetStuck { fet, closed, from } => [2 + closed as u32, fet, 0, from],
+      Fault::Bridge { net_a, net_b, from } => [4, net_a, net_b, from],
+    }
+  }
+}
+
+/// How many of a list of faults a stimulus set detects, from simulate_faults.
+pub struct FaultCoverage {
+  pub fault_count: u32,
+  /// In the order they were listed.
+  pub undetected: Vec<Fault>,
+}
+
+impl FaultCoverage {
+  /// The percentage of faults detected, which is 100 for an empty fault list.
+  pub fn percentage(&self) -> f64 {
+    match self.fault_count {
+      0 => 100.0,
+      count => 100.0 * (count - self.undetected.len() as u32) as f64 / count as f64,
+    }
+  }
+}
+
+/// Simulates the good circuit and each fault. A fault is detected if any graded net is at a different state than in
+/// the good circuit at some strobe tick (or any tick if strobe_ticks is empty) where the good circuit has it Low or
+/// High. Ticks index into the traces.
+///
+/// Netlists of nothing but equally sized FETs, signals, pull resistors, resistors and capacitors are parsed once and
+/// simulated bit-parallel: the good circuit and 63 faulty ones per batch, one per bit of a u64, until every fault
+/// in the batch is detected. Anything else gets a fresh simulator from make_simulator for each fault.
+pub fn simulate_faults(
+  make_simulator: &dyn Fn() -> Simulator,
+  faults: &[Fault],
+  graded_nets: &[Net],
+  strobe_ticks: &[u32],
+  duration: u32,
+  clock_divider: u32,
+) -> FaultCoverage {
+  let mut strobes = vec![strobe_ticks.is_empty(); duration as usize + 1];
+  for &tick in strobe_ticks {
+    if let Some(strobe) = strobes.get_mut(tick as usize) {
+      *strobe = true;
+    }
+  }
+  let simulator = make_simulator();
+  let undetected = match supports_bit_parallel(&simulator) {
+    true => faults.chunks(FAULTS_PER_BATCH).flat_map(|batch| {
+      let detected = simulate_batch(&simulator, batch, graded_nets, &strobes, duration, clock_divider);
+      batch.iter().enumerate().filter(move |(i, _)| detected & 1 << (i + 1) == 0).map(|(_, fault)| *fault)
+    }).collect(),
+    false => {
+      let good = simulator.run(graded_nets, duration, clock_divider).traces;
+      let detects = |traces: &[Vec<NetState>]| good.iter().zip(traces).any(|(good_trace, trace)| {
+        good_trace.iter().zip(trace).zip(&strobes).any(|((&expected, &state), &strobe)| {
+          strobe && expected.level().is_some() && state != expected
+        })
+      });
+      faults.iter().copied().filter(|&fault| {
+        let mut simulator = make_simulator();
+        simulator.inject_fault(fault);
+        !detects(&simulator.run(graded_nets, duration, clock_divider).traces)
+      }).collect()
+    }
+  };
+  FaultCoverage { fault_count: faults.len() as u32, undetected }
+}
+
+// Bit 0 of every word is the good circuit, and the rest are one fault each.
+const FAULTS_PER_BATCH: usize = 63;
+
+// Whether simulate_batch models everything in this simulator exactly as Simulator::run would.
+fn supports_bit_parallel(simulator: &Simulator) -> bool {
+  let mut fet_strength = None;
+  simulator.net_faults.is_empty() && simulator.netlist.components.iter().all(|component| match component {
+    Component::Fet{ size, .. } => *fet_strength.get_or_insert(size.strength()) == size.strength(),
+    Component::Signal{ .. } | Component::EdgeSignal{ .. } | Component::PullResistor{ .. } |
+    Component::Resistor{ .. } | Component::Capacitor{ .. } => true,
+    _ => false,
+  })
+}
+
+// The states of one net across a batch, one bit per circuit: Low is (1, 0), High (0, 1), ShootThrough (1, 1) and
+// Invalid (0, 0).
+#[derive(Clone, Copy, Default)]
+struct NetLanes {
+  low: u64,
+  high: u64,
+}
+
+// The drive on one net across a batch, with at most one bit set per circuit, and none for HighZ.
+#[derive(Clone, Copy, Default)]
+struct DriveLanes {
+  low: u64,
+  high: u64,
+  weak_low: u64,
+  weak_high: u64,
+  shoot_through: u64,
+  unknown: u64,
+}
+
+impl DriveLanes {
+  fn splat(drive: DriveType, lanes: u64) -> DriveLanes {
+    let mut drives = DriveLanes::default();
+    match drive {
+      DriveType::Low => drives.low = lanes,
+      DriveType::High => drives.high = lanes,
+      DriveType::WeakLow => drives.weak_low = lanes,
+      DriveType::WeakHigh => drives.weak_high = lanes,
+      DriveType::HighZ => (),
+      DriveType::ShootThrough => drives.shoot_through = lanes,
+      DriveType::Unknown => drives.unknown = lanes,
+    }
+    drives
+  }
+
+  // merge_drives in every circuit at once.
+  fn merge(self, other: DriveLanes) -> DriveLanes {
+    let shoot_through = self.shoot_through | other.shoot_through |
+      self.low & other.high | self.high & other.low |
+      self.weak_low & other.weak_high | self.weak_high & other.weak_low;
+    let unknown = !shoot_through & (self.unknown | other.unknown);
+    let low = !shoot_through & !unknown & (self.low | other.low);
+    let high = !shoot_through & !unknown & (self.high | other.high);
+    let weak = !shoot_through & !unknown & !low & !high;
+    DriveLanes {
+      low,
+      high,
+      weak_low: weak & (self.weak_low | other.weak_low),
+      weak_high: weak & (self.weak_high | other.weak_high),
+      shoot_through,
+      unknown,
+    }
+  }
+
+  fn any(self) -> u64 {
+    self.low | self.high | self.weak_low | self.weak_high | self.shoot_through | self.unknown
+  }
+}
+
+// Runs the good circuit in bit 0 alongside one faulty circuit per fault, following Simulator::run tick for tick.
+// Returns the mask of circuits whose faults were detected.
+fn simulate_batch(
+  simulator: &Simulator,
+  faults: &[Fault],
+  graded_nets: &[Net],
+  strobes: &[bool],
+  duration: u32,
+  clock_divider: u32,
+) -> u64 {
+  let netlist = &simulator.netlist;
+  let canonical = &netlist.canonical;
+  let net_count = netlist.net_count() as usize;
+  let components = &netlist.components;
+  let fet_count = components.iter().filter(|component| matches!(component, Component::Fet{ .. })).count();
+  let mut stuck_at: Vec<(usize, bool, u32, u64)> = Vec::new();
+  let mut bridges: Vec<(usize, usize, u32, u64)> = Vec::new();
+  let mut fet_faults: Vec<Vec<(bool, u32, u64)>> = vec![Vec::new(); fet_count];
+  let mut fault_lanes: u64 = 0;
+  for (i, fault) in faults.iter().enumerate() {
+    let lane = 1 << (i + 1);
+    fault_lanes |= lane;
+    match *fault {
+      Fault::StuckAt { net, level, from } => stuck_at.push((canonical[net as usize] as usize, level, from, lane)),
+      Fault::Bridge { net_a, net_b, from } =>
+        bridges.push((canonical[net_a as usize] as usize, canonical[net_b as usize] as usize, from, lane)),
+      Fault::FetStuck { fet, closed, from } => fet_faults.get_mut(fet as usize)
+        .unwrap_or_else(|| panic!("No FET number {} to inject a fault into", fet))
+        .push((closed, from, lane)),
+    }
+  }
+  let graded_nets: Vec<usize> = graded_nets.iter().map(|&net| canonical[net as usize] as usize).collect();
+  let settle_ticks = settle_ticks(components, netlist.net_capacitance.clone(), simulator.options);
+
+  let mut net_states = vec![NetLanes::default(); net_count];
+  let mut drives = vec![DriveLanes::default(); net_count];
+  let resistors: Vec<(Net, Net)> = components.iter().filter_map(|component| match component {
+    Component::Resistor{ net_a, net_b } => Some((*net_a, *net_b)),
+    _ => None,
+  }).collect();
+  let mut resistor_pulls = vec![(0u64, 0u64); net_count];
+  let mut settle_progress = vec![[0u32; 64]; net_count];
+  let mut settle_target = vec![NetLanes::default(); net_count];
+  let mut detected: u64 = 0;
+
+  for t in 0..duration {
+    let now = (t / clock_divider) as usize;
+    drives.fill(DriveLanes::default());
+
+    let mut fet_index = 0;
+    for component in components {
+      match component {
+        Component::Fet{ is_pfet, gate, drain, source, .. } => {
+          let (gate, source) = (net_states[*gate as usize], net_states[*source as usize]);
+          let (mut conducting, at_rail, drive) = match is_pfet {
+            false => (gate.high & !gate.low, source.low & !source.high, DriveType::Low),
+            true => (gate.low & !gate.high, source.high & !source.low, DriveType::High),
+          };
+          for &(closed, from, lane) in &fet_faults[fet_index] {
+            if t >= from {
+              conducting = match closed {
+                false => conducting & !lane,
+                true => conducting | lane,
+              };
+            }
+          }
+          let drain = *drain as usize;
+          drives[drain] = drives[drain].merge(DriveLanes::splat(drive, conducting & at_rail));
+          fet_index += 1;
+        }
+        Component::PullResistor{ is_pull_down, net } => {
+          let drive = match is_pull_down {
+            true => DriveType::WeakLow,
+            false => DriveType::WeakHigh,
+          };
+          drives[*net as usize] = drives[*net as usize].merge(DriveLanes::splat(drive, !0));
+        }
+        Component::Signal{ pattern, repeat, net, timing } => {
+          let drive = signal_output(pattern, *repeat, timing.as_ref(), t, now);
+          drives[*net as usize] = drives[*net as usize].merge(DriveLanes::splat(drive, !0));
+        }
+        Component::EdgeSignal{ edges, net } => {
+          let drive = edge_signal_output(edges, t);
+          drives[*net as usize] = drives[*net as usize].merge(DriveLanes::splat(drive, !0));
+        }
+        _ => (),
+      }
+    }
+
+    if !resistors.is_empty() {
+      resistor_pulls.fill((0, 0));
+      spread_resistor_pulls(&resistors, &mut resistor_pulls, |net, pulls| {
+        let (driven, state) = (drives[net].any(), net_states[net]);
+        (driven & state.low & !state.high | !driven & pulls.0, driven & state.high & !state.low | !driven & pulls.1)
+      }, |a, b| (a.0 | b.0, a.1 | b.1));
+      for (drive, pulls) in drives.iter_mut().zip(&resistor_pulls) {
+        let undriven = !drive.any();
+        drive.weak_low |= undriven & pulls.0 & !pulls.1;
+        drive.weak_high |= undriven & pulls.1 & !pulls.0;
+      }
+    }
+
+    for &(net_a, net_b, from, lane) in &bridges {
+      if t >= from {
+        let (a, b) = (drives[net_a], drives[net_b]);
+        let bridged = a.merge(b);
+        let select = |bits: u64, mine: u64| bits & lane | mine & !lane;
+        for (net, mine) in [(net_a, a), (net_b, b)] {
+          drives[net] = DriveLanes {
+            low: select(bridged.low, mine.low),
+            high: select(bridged.high, mine.high),
+            weak_low: select(bridged.weak_low, mine.weak_low),
+            weak_high: select(bridged.weak_high, mine.weak_high),
+            shoot_through: select(bridged.shoot_through, mine.shoot_through),
+            unknown: select(bridged.unknown, mine.unknown),
+          };
+        }
+      }
+    }
+
+    // The same transitions as Simulator::run, in every circuit at once.
+    for net in 0..net_count {
+      let (last, drive) = (net_states[net], drives[net]);
+      let last_invalid = !last.low & !last.high;
+      let shoot_through = last.low & last.high | drive.shoot_through;
+      let mut low = !shoot_through & (last_invalid | last.low & !last.high) & (drive.low | drive.weak_low);
+      let mut high = !shoot_through & (last_invalid | last.high & !last.low) & (drive.high | drive.weak_high);
+      if settle_ticks[net] > 0 {
+        let settling = last_invalid & (low | high);
+        let target = &mut settle_target[net];
+        target.low &= settling;
+        target.high &= settling;
+        let mut lanes = settling;
+        while lanes != 0 {
+          let lane = 1 << lanes.trailing_zeros();
+          lanes &= !lane;
+          if (target.low ^ low) & lane != 0 || (target.high ^ high) & lane != 0 {
+            target.low = target.low & !lane | low & lane;
+            target.high = target.high & !lane | high & lane;
+            settle_progress[net][lane.trailing_zeros() as usize] = 0;
+          }
+          let progress = &mut settle_progress[net][lane.trailing_zeros() as usize];
+          if *progress < settle_ticks[net] {
+            *progress += 1;
+            low &= !lane;
+            high &= !lane;
+          }
+        }
+      }
+      net_states[net] = NetLanes { low: shoot_through | low, high: shoot_through | high };
+    }
+    for &(net, level, from, lane) in &stuck_at {
+      if t >= from {
+        let state = &mut net_states[net];
+        state.low = state.low & !lane | if level { 0 } else { lane };
+        state.high = state.high & !lane | if level { lane } else { 0 };
+      }
+    }
+
+    // This tick's states are trace tick t + 1.
+    if strobes.get(t as usize + 1) == Some(&true) {
+      for &net in &graded_nets {
+        let state = net_states[net];
+        let (good_low, good_high) = ((state.low & 1).wrapping_neg(), (state.high & 1).wrapping_neg());
+        if good_low != good_high {
+          detected |= (state.low ^ good_low | state.high ^ good_high) & fault_lanes;
+        }
+      }
+      if detected == fault_lanes {
+        break;
+      }
+    }
+  }
+  detected
+}
+
+// A FET with a stuck fault. Once the fault is active its gate no longer matters.
+pub struct FaultyFet {
+  is_pfet: bool,
+  pub gate: Net,
+  pub drain: Net,
+  source: Net,
+  pub size: FetSize,
+  closed: bool,
+  from: u32,
+}
+
+impl FaultyFet {
+  pub fn new(is_pfet: bool, gate: Net, drain: Net, source: Net, size: FetSize, closed: bool, from: u32) -> FaultyFet {
+    FaultyFet { is_pfet, gate, drain, source, size, closed, from }
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    vec![self.gate, self.drain, self.source]
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    self.gate = f(self.gate);
+    self.drain = f(self.drain);
+    self.source = f(self.source);
+  }
+
+  pub fn step(&self, t: u32, net_states: &[NetState]) -> DriveType {
+    let (on_level, rail_state, drive) = match self.is_pfet {
+      false => (NetState::High, NetState::Low, DriveType::Low),
+      true => (NetState::Low, NetState::High, DriveType::High),
+    };
+    let conducting = match t >= self.from {
+      true => self.closed,
+      false => net_states[self.gate as usize] == on_level,
+    };
+    match conducting && net_states[self.source as usize] == rail_state {
+      true => drive,
+      false => DriveType::HighZ,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::testing::{fet, rails, seeded_below, signal, SENTINEL};
+  use super::super::{Net, Simulator};
+  use super::{simulate_faults, Fault};
+
+  // What simulate_faults should find, from simulating each fault on its own.
+  fn undetected_one_at_a_time(
+    make_simulator: &dyn Fn() -> Simulator,
+    faults: &[Fault],
+    graded_nets: &[Net],
+    strobe_ticks: &[u32],
+    clock_divider: u32,
+  ) -> Vec<Fault> {
+    let good = make_simulator().run(graded_nets, 60, clock_divider).traces;
+    let strobed = |tick: usize| strobe_ticks.is_empty() || strobe_ticks.contains(&(tick as u32));
+    faults.iter().copied().filter(|&fault| {
+      let mut simulator = make_simulator();
+      simulator.inject_fault(fault);
+      let traces = simulator.run(graded_nets, 60, clock_divider).traces;
+      !good.iter().zip(&traces).any(|(good_trace, trace)| (0..good_trace.len()).any(|tick| {
+        strobed(tick) && good_trace[tick].level().is_some() && trace[tick] != good_trace[tick]
+      }))
+    }).collect()
+  }
+
+  #[test]
+  fn bit_parallel_matches_one_at_a_time() {
+    let mut below = seeded_below();
+    for _ in 0..100 {
+      // Rails, stimulus with every pattern var, and then random FETs, pull resistors, resistors and capacitors.
+      let net_count = 6 + below(10);
+      let mut description = rails();
+      for net in 2..5 {
+        let pattern: Vec<u32> = (0..1 + below(6)).map(|_| below(6)).collect();
+        description.extend(signal(net, &pattern, below(2) == 0));
+      }
+      description.extend([17, 5, 2, below(20), below(6), 20 + below(20), below(6), SENTINEL]);
+      for _ in 0..4 + below(20) {
+        description.extend(fet(below(2) == 1, below(net_count), 2 + below(net_count - 2), below(net_count)));
+      }
+      for _ in 0..below(3) {
+        description.extend([3, below(2), 2 + below(net_count - 2), SENTINEL]);
+      }
+      for _ in 0..below(3) {
+        description.extend([6, below(net_count), below(net_count), 1000, SENTINEL]);
+      }
+      for _ in 0..below(3) {
+        description.extend([7, below(net_count), below(net_count), below(60), SENTINEL]);
+      }
+      let femtofarads_per_fanout = [0, 0, below(30)][below(3) as usize];
+      let make_simulator = || Simulator::new(&description, net_count).with_femtofarads_per_fanout(femtofarads_per_fanout);
+
+      // Late faults and bridges too, which single_stuck_faults never lists.
+      let mut faults = make_simulator().single_stuck_faults();
+      for _ in 0..below(10) {
+        faults.push(Fault::Bridge { net_a: below(net_count), net_b: below(net_count), from: below(30) });
+      }
+      for _ in 0..below(5) {
+        faults.push(Fault::StuckAt { net: below(net_count), level: below(2) == 0, from: below(30) });
+        faults.push(Fault::FetStuck { fet: 0, closed: below(2) == 0, from: below(30) });
+      }
+      let graded_nets: Vec<Net> = (0..net_count).filter(|_| below(2) == 0).collect();
+      let strobe_ticks: Vec<u32> = (0..below(2) * below(8)).map(|_| below(70)).collect();
+      let clock_divider = 1 + below(4);
+      let coverage = simulate_faults(&make_simulator, &faults, &graded_nets, &strobe_ticks, 60, clock_divider);
+      let expected = undetected_one_at_a_time(&make_simulator, &faults, &graded_nets, &strobe_ticks, clock_divider);
+      assert_eq!(coverage.undetected, expected);
+    }
+  }
+}
@@ -0,0 +1,158 @@
+
+use std::collections::VecDeque;
+use super::{DriveType, Net, NetState};
+
+#[derive(Clone, Copy)]
+pub enum GateKind {
+  Not,
+  Nand,
+  Nor,
+  And,
+  Or,
+  Xor,
+  // The first k inputs are select bits (least significant first), followed by 2^k data inputs.
+  Mux,
+}
+
+impl GateKind {
+  pub fn from_opcode(kind: u32) -> GateKind {
+    match kind {
+      0 => GateKind::Not,
+      1 => GateKind::Nand,
+      2 => GateKind::Nor,
+      3 => GateKind::And,
+      4 => GateKind::Or,
+      5 => GateKind::Xor,
+      6 => GateKind::Mux,
+      _ => panic!("Invalid gate kind: {}", kind),
+    }
+  }
+}
+
+// Number of select bits for a mux with this many inputs in total, if it's a valid mux.
+pub fn mux_select_bits(input_count: usize) -> Option<usize> {
+  (0..24).find(|k| k + (1 << k) == input_count)
+}
+
+// Evaluates a gate with X propagation. None means the output is unknown.
+// A controlling input (Low for AND-like gates, High for OR-like gates) decides the output even if other inputs are unknown.
+pub fn evaluate(kind: GateKind, inputs: &[Net], net_states: &[NetState]) -> Option<bool> {
+  let mut levels = inputs.iter().map(|net| net_states[*net as usize].level());
+  match kind {
+    GateKind::Not => net_states[inputs[0] as usize].level().map(|x| !x),
+    GateKind::And | GateKind::Nand => {
+      let mut result = Some(true);
+      for x in levels {
+        match x {
+          Some(false) => { result = Some(false); break; }
+          None => result = None,
+          Some(true) => (),
+        }
+      }
+      match kind {
+        GateKind::Nand => result.map(|x| !x),
+        _ => result,
+      }
+    }
+    GateKind::Or | GateKind::Nor => {
+      let mut result = Some(false);
+      for x in levels {
+        match x {
+          Some(true) => { result = Some(true); break; }
+          None => result = None,
+          Some(false) => (),
+        }
+      }
+      match kind {
+        GateKind::Nor => result.map(|x| !x),
+        _ => result,
+      }
+    }
+    GateKind::Xor => levels.try_fold(false, |acc, x| Some(acc ^ x?)),
+    GateKind::Mux => {
+      let select_bits = mux_select_bits(inputs.len()).unwrap();
+      let (select, data) = inputs.split_at(select_bits);
+      let mut known_select: usize = 0;
+      let mut unknown_select: usize = 0;
+      for (bit_position, net) in select.iter().enumerate() {
+        match net_states[*net as usize].level() {
+          Some(true) => known_select |= 1 << bit_position,
+          Some(false) => (),
+          None => unknown_select |= 1 << bit_position,
+        }
+      }
+      // With unknown select bits the output is only known if every data input we might be selecting agrees.
+      let mut result = None;
+      for (index, net) in data.iter().enumerate() {
+        if index & !unknown_select != known_select {
+          continue;
+        }
+        let x = net_states[*net as usize].level()?;
+        match result {
+          None => result = Some(x),
+          Some(y) if y != x => return None,
+          Some(_) => (),
+        }
+      }
+      result
+    }
+  }
+}
+
+pub struct Gate {
+  kind: GateKind,
+  inputs: Vec<Net>,
+  pub output: Net,
+  // Drives computed on the last delay - 1 ticks, oldest first, waiting to come out.
+  history: VecDeque<DriveType>,
+}
+
+impl Gate {
+  // A delay of one tick matches a gate built from FETs, where the output changes the tick after the inputs do.
+  pub fn new(kind: GateKind, delay: u32, inputs: Vec<Net>, output: Net) -> Gate {
+    match kind {
+      GateKind::Not if inputs.len() != 1 => panic!("A NOT gate must have exactly one input, got {}", inputs.len()),
+      GateKind::Mux if mux_select_bits(inputs.len()).is_none() =>
+        panic!("A mux must have k select inputs then 2^k data inputs, got {} inputs", inputs.len()),
+      _ => (),
+    }
+    let history_length = delay.max(1) as usize - 1;
+    Gate { kind, inputs, output, history: vec![DriveType::HighZ; history_length].into() }
+  }
+
+  pub fn inputs(&self) -> &[Net] {
+    &self.inputs
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    let mut nets = self.inputs.clone();
+    nets.push(self.output);
+    nets
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    for net in self.inputs.iter_mut() {
+      *net = f(*net);
+    }
+    self.output = f(self.output);
+  }
+
+  // The drive this gate settles to with its inputs held at these states, ignoring its delay.
+  pub fn settled_drive(&self, net_states: &[NetState]) -> DriveType {
+    match evaluate(self.kind, &self.inputs, net_states) {
+      Some(false) => DriveType::Low,
+      Some(true) => DriveType::High,
+      None => DriveType::Unknown,
+    }
+  }
+
+  // Returns the drive this gate puts on its output this tick.
+  pub fn step(&mut self, net_states: &[NetState]) -> DriveType {
+    let drive = self.settled_drive(net_states);
+    if self.history.is_empty() {
+      return drive;
+    }
+    self.history.push_back(drive);
+    self.history.pop_front().unwrap()
+  }
+}
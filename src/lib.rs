@@ -1,24 +1,77 @@
 
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::iter::FromIterator;
-use once_cell::sync::OnceCell;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::Mutex;
 use wasm_bindgen::prelude::*;
 
-const SUBSTEPS: u32 = 1;
+pub use atpg::{generate_tests, TestSet};
+pub use device::{Device, DeviceTick};
+pub use display::Framebuffer;
+pub use fault::{simulate_faults, Fault, FaultCoverage};
+
+mod abstraction;
+mod atpg;
+mod device;
+mod display;
+mod fault;
+mod gates;
+mod netlist;
+mod peripherals;
+mod queue;
+mod sequential;
+mod stimulus;
+mod testbench;
 
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-static mut GLOBAL_INDICES: Vec<u32> = Vec::new();
-static mut GLOBAL_FIRST_SHOOT_THROUGH: i32 = -1;
+static GLOBAL_OPTIONS: Mutex<SimulationOptions> = Mutex::new(SimulationOptions::DEFAULT);
+static GLOBAL_INDICES: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static GLOBAL_FIRST_SHOOT_THROUGH: Mutex<i32> = Mutex::new(-1);
+static GLOBAL_SRAM_CONTENTS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static GLOBAL_SRAM_ACCESS_LOG: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static GLOBAL_WARNINGS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static GLOBAL_NET_NAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static GLOBAL_CANONICAL_NETS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static GLOBAL_CONSOLE_OUTPUT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static GLOBAL_FRAMEBUFFERS: Mutex<Vec<Framebuffer>> = Mutex::new(Vec::new());
+static GLOBAL_FAULTS: Mutex<Vec<Fault>> = Mutex::new(Vec::new());
+static GLOBAL_UNDETECTED_FAULTS: Mutex<Vec<Fault>> = Mutex::new(Vec::new());
+
+thread_local! {
+  // JS objects can't be shared between threads, so unlike the other globals these live in a thread local.
+  static SCRIPTED_DEVICES: RefCell<Vec<device::ScriptedDevice>> = const { RefCell::new(Vec::new()) };
+}
+
+pub type Net = u32;
 
-type Net = u32;
+// Stands in for an optional net that isn't connected, in opcodes that have them.
+const NO_NET: u32 = u32::MAX;
+
+fn optional_net(net: u32) -> Option<Net> {
+  match net {
+    NO_NET => None,
+    net => Some(net),
+  }
+}
 
 enum Component {
-  Fet { is_pfet: bool, gate: Net, drain: Net, source: Net },
-  Signal { pattern: Vec<DriveType>, repeat: bool, net: Net },
+  Fet { is_pfet: bool, gate: Net, drain: Net, source: Net, size: FetSize },
+  Signal { pattern: Vec<DriveType>, repeat: bool, net: Net, timing: Option<SignalTiming> },
+  // Drives each value from its tick until the next one's, sorted by tick. Undriven before the first.
+  EdgeSignal { edges: Vec<(u32, DriveType)>, net: Net },
   PullResistor { is_pull_down: bool, net: Net },
+  Resistor { net_a: Net, net_b: Net },
+  Capacitor { net_a: Net, net_b: Net, capacitance: u32 },
+  Gate(gates::Gate),
+  Sequential(sequential::Sequential),
+  Lfsr(stimulus::Lfsr),
+  HandshakeResponder(testbench::HandshakeResponder),
+  TriggeredPulse(testbench::TriggeredPulse),
+  BusMemory(testbench::BusMemory),
+  Cell(abstraction::CmosCell),
+  FaultyFet(fault::FaultyFet),
+  Device(device::PluggedDevice),
   Sram {
     address_bit_count: u32,
     word_size: u32,
@@ -27,29 +80,339 @@ enum Component {
     bus_in_nets: Vec<Net>,
     bus_out_nets: Vec<Net>,
     write_enable_net: Net,
+    last_access: Option<SramAccess>,
+    // The bad write strict mode last warned about, or None if the last tick wasn't one.
+    reported_bad_write: Option<BadWrite>,
+    timing: Option<SramTiming>,
   },
 }
 
+impl Component {
+  // Every net this component is connected to, for passes that need to know what touches what.
+  fn nets(&self) -> Vec<Net> {
+    match self {
+      Component::Fet{ gate, drain, source, .. } => vec![*gate, *drain, *source],
+      Component::Signal{ net, .. } | Component::EdgeSignal{ net, .. } | Component::PullResistor{ net, .. } => vec![*net],
+      Component::Resistor{ net_a, net_b } | Component::Capacitor{ net_a, net_b, .. } => vec![*net_a, *net_b],
+      Component::Gate(gate) => gate.nets(),
+      Component::Sequential(element) => element.nets(),
+      Component::Lfsr(lfsr) => lfsr.nets(),
+      Component::HandshakeResponder(responder) => responder.nets(),
+      Component::TriggeredPulse(pulse) => pulse.nets(),
+      Component::BusMemory(memory) => memory.nets(),
+      Component::Cell(cell) => cell.nets(),
+      Component::FaultyFet(fet) => fet.nets(),
+      Component::Device(device) => device.nets(),
+      Component::Sram{ address_nets, bus_in_nets, bus_out_nets, write_enable_net, .. } =>
+        [&address_nets[..], &bus_in_nets[..], &bus_out_nets[..], &[*write_enable_net]].concat(),
+    }
+  }
+
+  // Every pin that loads a net as an input: FET gates and the inputs of behavioral components. A net connected to
+  // several of one component's pins appears once per pin.
+  fn input_nets(&self) -> Vec<Net> {
+    match self {
+      Component::Fet{ gate, .. } => vec![*gate],
+      Component::Signal{ .. } | Component::EdgeSignal{ .. } | Component::PullResistor{ .. } |
+      Component::Resistor{ .. } | Component::Capacitor{ .. } | Component::Lfsr(_) => Vec::new(),
+      Component::Gate(gate) => gate.inputs().to_vec(),
+      Component::Sequential(element) => element.inputs(),
+      Component::HandshakeResponder(responder) => responder.inputs(),
+      Component::TriggeredPulse(pulse) => pulse.inputs(),
+      Component::BusMemory(memory) => memory.inputs(),
+      Component::Cell(cell) => cell.inputs(),
+      Component::FaultyFet(fet) => vec![fet.gate],
+      Component::Device(device) => device.inputs().to_vec(),
+      Component::Sram{ address_nets, bus_in_nets, write_enable_net, .. } =>
+        [&address_nets[..], &bus_in_nets[..], &[*write_enable_net]].concat(),
+    }
+  }
+
+  // Renames every net this component is connected to, for flattening subcircuits and merging wired nets.
+  fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    match self {
+      Component::Fet{ gate, drain, source, .. } => {
+        *gate = f(*gate);
+        *drain = f(*drain);
+        *source = f(*source);
+      }
+      Component::Signal{ net, .. } | Component::EdgeSignal{ net, .. } | Component::PullResistor{ net, .. } =>
+        *net = f(*net),
+      Component::Resistor{ net_a, net_b } | Component::Capacitor{ net_a, net_b, .. } => {
+        *net_a = f(*net_a);
+        *net_b = f(*net_b);
+      }
+      Component::Gate(gate) => gate.map_nets(f),
+      Component::Sequential(element) => element.map_nets(f),
+      Component::Lfsr(lfsr) => lfsr.map_nets(f),
+      Component::HandshakeResponder(responder) => responder.map_nets(f),
+      Component::TriggeredPulse(pulse) => pulse.map_nets(f),
+      Component::BusMemory(memory) => memory.map_nets(f),
+      Component::Cell(cell) => cell.map_nets(f),
+      Component::FaultyFet(fet) => fet.map_nets(f),
+      Component::Device(device) => device.map_nets(f),
+      Component::Sram{ address_nets, bus_in_nets, bus_out_nets, write_enable_net, .. } => {
+        for net in address_nets.iter_mut().chain(bus_in_nets.iter_mut()).chain(bus_out_nets.iter_mut()) {
+          *net = f(*net);
+        }
+        *write_enable_net = f(*write_enable_net);
+      }
+    }
+  }
+}
+
+// Settings that persist across calls to perform_simulation, changed with the set_* functions below.
+#[derive(Clone, Copy)]
+struct SimulationOptions {
+  log_sram_accesses: bool,
+  strict_sram: bool,
+  pessimistic_sram: bool,
+  femtofarads_per_tick: u32,
+  // Zero unless load-dependent delays are on.
+  femtofarads_per_fanout: u32,
+  abstract_gates: bool,
+}
+
+impl SimulationOptions {
+  const DEFAULT: SimulationOptions = SimulationOptions {
+    log_sram_accesses: false,
+    strict_sram: false,
+    pessimistic_sram: false,
+    femtofarads_per_tick: 10,
+    femtofarads_per_fanout: 0,
+    abstract_gates: true,
+  };
+}
+
+// Each warning is reported as four words: tick, kind, index, detail.
+// The meaning of index and detail depends on the kind.
+#[derive(Clone, Copy)]
+#[repr(u32)]
+enum WarningKind {
+  // Strict mode reports these two once when a bad write starts, and again whenever its address or data changes,
+  // rather than on every tick write enable is held.
+  // index: SRAM index, detail: mask of the address bits that were invalid.
+  SramWriteInvalidAddress = 0,
+  // index: SRAM index, detail: the address written.
+  SramWriteInvalidData = 1,
+  // index: SRAM index, detail: how many ticks the address was stable before write enable rose (zero if it changed mid-write).
+  SramAddressSetupViolation = 2,
+  // index: SRAM index, detail: how many ticks the data was stable before write enable fell.
+  SramDataSetupViolation = 3,
+  // index: SRAM index, detail: how many ticks after write enable fell the address or data changed.
+  SramHoldViolation = 4,
+  // index: flip-flop or latch index, detail: how many ticks d was stable before the capturing (or closing) edge.
+  SequentialSetupViolation = 5,
+  // index: flip-flop or latch index, detail: how many ticks after the edge d changed.
+  SequentialHoldViolation = 6,
+  // index: device index, detail: whatever the device reported. See Device.
+  DeviceWarning = 7,
+  // index: device index, detail: mask of the data bits that were invalid, or all ones if the address was.
+  // Reported by consoles and timers for a write the peripheral had to ignore.
+  PeripheralInvalidWrite = 8,
+  // index: device index, detail: the bits received so far. The byte is dropped.
+  UartFramingError = 9,
+  // index: device index, detail: unused. Reported once per read of an input data register with nothing waiting.
+  InputReadWhenEmpty = 10,
+  // index: device index, detail: the low 32 bits of the word that was dropped.
+  QueueOverflow = 11,
+  // index: device index, detail: unused.
+  QueueUnderflow = 12,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct SramAccess {
+  address: u32,
+  is_write: bool,
+  value: u32,
+  invalid_mask: u32,
+}
+
+// A write strict mode warns about. Only the address is known for a write to an Invalid address.
+#[derive(Clone, Copy, PartialEq)]
+struct BadWrite {
+  address: u32,
+  address_invalid_mask: u32,
+  data: u32,
+  data_invalid_mask: u32,
+}
+
+// A FET's width and length, in any units as long as they're consistent. The digital model only uses W/L, as the
+// strength with which the FET pulls its drain when it fights other FETs.
+#[derive(Clone, Copy)]
+struct FetSize {
+  width: u32,
+  length: u32,
+}
+
+impl FetSize {
+  const DEFAULT: FetSize = FetSize { width: 1, length: 1 };
+
+  fn strength(self) -> f64 {
+    self.width as f64 / self.length as f64
+  }
+}
+
+// FETs pulling a net one way only win outright against FETs pulling it the other way if they're this many times as strong.
+const CONTENTION_RATIO: f64 = 2.0;
+
+// Only signals from the timed signal opcode have one of these; plain signals step every clock_divider ticks from tick 0.
+struct SignalTiming {
+  // Ticks per pattern value.
+  period: u32,
+  // Ticks before the first pattern value starts.
+  phase: u32,
+  // If set, each pattern value is only driven for this many ticks at the start of its period, then rest is driven.
+  duty: Option<u32>,
+  rest: DriveType,
+}
+
+// Only SRAMs from the timed SRAM opcode have one of these; plain SRAMs are idealized.
+struct SramTiming {
+  read_latency: u32,
+  setup_ticks: u32,
+  hold_ticks: u32,
+  // The words read over the last read_latency ticks, oldest first. None where there was no valid read.
+  read_pipeline: VecDeque<Option<Vec<NetState>>>,
+  last_address: (u32, u32),
+  address_changed_at: u32,
+  // When the address changed before that, so a change on the tick a write ends can be told apart from the write.
+  address_previously_changed_at: u32,
+  last_data: (u32, u32),
+  data_changed_at: u32,
+  data_previously_changed_at: u32,
+  write_started_at: Option<u32>,
+  write_ended_at: Option<u32>,
+}
+
+impl SramTiming {
+  fn new(read_latency: u32, setup_ticks: u32, hold_ticks: u32) -> SramTiming {
+    SramTiming {
+      read_latency,
+      setup_ticks,
+      hold_ticks,
+      read_pipeline: VecDeque::new(),
+      last_address: (0, u32::MAX),
+      address_changed_at: 0,
+      address_previously_changed_at: 0,
+      last_data: (0, u32::MAX),
+      data_changed_at: 0,
+      data_previously_changed_at: 0,
+      write_started_at: None,
+      write_ended_at: None,
+    }
+  }
+
+  // Called once per tick with the current address, data and write enable, before the access itself happens.
+  fn check_write_timing(
+    &mut self,
+    t: u32,
+    address: (u32, u32),
+    data: (u32, u32),
+    write_enable: NetState,
+    sram_index: u32,
+    warnings: &mut Vec<u32>,
+  ) {
+    if address != self.last_address {
+      self.last_address = address;
+      self.address_previously_changed_at = self.address_changed_at;
+      self.address_changed_at = t;
+    }
+    if data != self.last_data {
+      self.last_data = data;
+      self.data_previously_changed_at = self.data_changed_at;
+      self.data_changed_at = t;
+    }
+
+    // Anything changing inside the hold window after a write is a violation, reported once per write.
+    if let Some(write_ended_at) = self.write_ended_at {
+      if t - write_ended_at >= self.hold_ticks {
+        self.write_ended_at = None;
+      } else if self.address_changed_at == t || self.data_changed_at == t {
+        warnings.extend_from_slice(&[t, WarningKind::SramHoldViolation as u32, sram_index, t - write_ended_at]);
+        self.write_ended_at = None;
+      }
+    }
+
+    match (self.write_started_at, write_enable) {
+      (None, NetState::High) => self.write_started_at = Some(t),
+      (Some(_), NetState::High) => (),
+      (Some(write_started_at), _) => {
+        // The write just ended, so check that the address was held from setup_ticks before the rising edge,
+        // and the data for setup_ticks before the falling edge. The last tick written was t - 1, so anything
+        // changing on tick t itself is left to the hold check.
+        let stable_since = |changed_at: u32, previously_changed_at: u32| match changed_at == t {
+          true => previously_changed_at,
+          false => changed_at,
+        };
+        let address_stable_since = stable_since(self.address_changed_at, self.address_previously_changed_at);
+        let data_stable_since = stable_since(self.data_changed_at, self.data_previously_changed_at);
+        if address_stable_since as i64 > write_started_at as i64 - self.setup_ticks as i64 {
+          let setup = write_started_at.saturating_sub(address_stable_since);
+          warnings.extend_from_slice(&[t, WarningKind::SramAddressSetupViolation as u32, sram_index, setup]);
+        }
+        if t - data_stable_since < self.setup_ticks {
+          warnings.extend_from_slice(&[t, WarningKind::SramDataSetupViolation as u32, sram_index, t - data_stable_since]);
+        }
+        self.write_started_at = None;
+        self.write_ended_at = Some(t);
+        if self.hold_ticks > 0 && (self.address_changed_at == t || self.data_changed_at == t) {
+          warnings.extend_from_slice(&[t, WarningKind::SramHoldViolation as u32, sram_index, 0]);
+          self.write_ended_at = None;
+        }
+      }
+      (None, _) => (),
+    }
+  }
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
-enum DriveType {
+pub enum DriveType {
   Low = 0,
   High = 1,
   WeakLow = 2,
   WeakHigh = 3,
   HighZ = 4,
   ShootThrough = 5,
+  // A strong drive to an unknown level, as produced by behavioral components with unknown inputs.
+  Unknown = 6,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 #[repr(u8)]
-enum NetState {
+pub enum NetState {
   Invalid = 0,
   Low = 1,
   High = 2,
   ShootThrough = 3,
 }
 
+impl NetState {
+  pub fn level(self) -> Option<bool> {
+    match self {
+      NetState::Low => Some(false),
+      NetState::High => Some(true),
+      _ => None,
+    }
+  }
+}
+
+// Packs a word of bits (least significant first) into (value, invalid_mask).
+// Any bit that isn't cleanly Low or High is reported as a zero in value and a one in invalid_mask.
+fn pack_word<'a>(bits: impl Iterator<Item = &'a NetState>) -> (u32, u32) {
+  let mut value: u32 = 0;
+  let mut invalid_mask: u32 = 0;
+  for (bit_position, bit) in bits.enumerate() {
+    match bit {
+      NetState::Low => (),
+      NetState::High => value |= 1 << bit_position,
+      _ => invalid_mask |= 1 << bit_position,
+    }
+  }
+  (value, invalid_mask)
+}
+
 fn merge_drives(a: DriveType, b: DriveType) -> DriveType {
   match (a, b) {
     // Let strongs override weaks.
@@ -74,14 +437,170 @@ fn merge_drives(a: DriveType, b: DriveType) -> DriveType {
     (DriveType::Low, DriveType::High) | (DriveType::High, DriveType::Low) |
     (_, DriveType::ShootThrough) | (DriveType::ShootThrough, _)
       => DriveType::ShootThrough,
+
+    // An unknown drive could be fighting anything else, so the result is unknown too.
+    (DriveType::Unknown, _) | (_, DriveType::Unknown) => DriveType::Unknown,
+  }
+}
+
+fn level_drive(level: bool) -> DriveType {
+  match level {
+    false => DriveType::Low,
+    true => DriveType::High,
+  }
+}
+
+// The drive on a net from FETs pulling it low and high, given the strongest FET pulling each way. Parallel FETs
+// don't add up, so equally sized FETs never overpower each other however many there are, and shoot through just as
+// merge_drives would have it.
+fn fet_contention(low_strength: f64, high_strength: f64) -> DriveType {
+  match (low_strength > 0.0, high_strength > 0.0) {
+    (false, false) => DriveType::HighZ,
+    (true, false) => DriveType::Low,
+    (false, true) => DriveType::High,
+    (true, true) if low_strength >= CONTENTION_RATIO * high_strength => DriveType::Low,
+    (true, true) if high_strength >= CONTENTION_RATIO * low_strength => DriveType::High,
+    (true, true) => DriveType::ShootThrough,
+  }
+}
+
+// The drive resistors put on a net, given whether any are pulling it low and high. Resistors are weaker than
+// anything else, so this only applies to nets nothing else drives, and resistors pulling both ways leave it Invalid.
+fn resistive_drive((pulled_low, pulled_high): (bool, bool)) -> DriveType {
+  match (pulled_low, pulled_high) {
+    (true, false) => DriveType::WeakLow,
+    (false, true) => DriveType::WeakHigh,
+    _ => DriveType::HighZ,
+  }
+}
+
+// Spreads pulls across resistors until nothing changes, merging everything pulling a net with merge. pass_on gives
+// what a net pulls its neighbors towards, given what resistors pull it towards: its own level if anything else drives
+// it, and otherwise just what it's pulled towards. That way a chain of resistors between undriven nets relays a level,
+// but nets joined only by resistors can't hold each other up and float together.
+fn spread_resistor_pulls<T: Copy + PartialEq>(
+  resistors: &[(Net, Net)],
+  pulls: &mut [T],
+  pass_on: impl Fn(usize, T) -> T,
+  merge: impl Fn(T, T) -> T,
+) {
+  let mut changed = true;
+  while changed {
+    changed = false;
+    for &(net_a, net_b) in resistors {
+      for (from, to) in [(net_a as usize, net_b as usize), (net_b as usize, net_a as usize)] {
+        let pulled = merge(pulls[to], pass_on(from, pulls[from]));
+        if pulled != pulls[to] {
+          pulls[to] = pulled;
+          changed = true;
+        }
+      }
+    }
+  }
+}
+
+// Adds a FET's drive to its drain. When FETs come in different sizes, fet_strengths holds the strength of the strongest
+// FET pulling each net (low, high), and is resolved with fet_contention once every component has stepped.
+// Otherwise it's empty.
+fn drive_from_fet(drives: &mut [DriveType], fet_strengths: &mut [(f64, f64)], drain: Net, drive: DriveType, size: FetSize) {
+  let drain = drain as usize;
+  match (fet_strengths.is_empty(), drive) {
+    (false, DriveType::Low) => fet_strengths[drain].0 = fet_strengths[drain].0.max(size.strength()),
+    (false, DriveType::High) => fet_strengths[drain].1 = fet_strengths[drain].1.max(size.strength()),
+    _ => drives[drain] = merge_drives(drives[drain], drive),
+  }
+}
+
+// What a Signal drives on tick t, where now is t / clock_divider.
+fn signal_output(pattern: &[DriveType], repeat: bool, timing: Option<&SignalTiming>, t: u32, now: usize) -> DriveType {
+  let value_at = |step: usize| pattern[match repeat {
+    true => step % pattern.len(),
+    false => std::cmp::min(step, pattern.len() - 1),
+  }];
+  match timing {
+    None => value_at(now),
+    Some(timing) => match (t.checked_sub(timing.phase), timing.duty) {
+      // Before its phase offset a signal holds its first value, or rests if it has a duty cycle.
+      (None, None) => pattern[0],
+      (None, Some(_)) => timing.rest,
+      (Some(elapsed), Some(duty)) if elapsed % timing.period >= duty => timing.rest,
+      (Some(elapsed), _) => value_at((elapsed / timing.period) as usize),
+    },
+  }
+}
+
+// What an EdgeSignal drives on tick t.
+fn edge_signal_output(edges: &[(u32, DriveType)], t: u32) -> DriveType {
+  match edges.partition_point(|(tick, _)| *tick <= t) {
+    0 => DriveType::HighZ,
+    next => edges[next - 1].1,
+  }
+}
+
+// How many extra ticks each net must be driven the same way to come out of Invalid, from its capacitance and the
+// options. Shared by Simulator::run and fault simulation.
+fn settle_ticks(components: &[Component], mut net_capacitance: Vec<u32>, options: SimulationOptions) -> Vec<u32> {
+  for component in components {
+    if let Component::Capacitor{ net_a, net_b, capacitance } = component {
+      net_capacitance[*net_a as usize] += capacitance;
+      net_capacitance[*net_b as usize] += capacitance;
+    }
+  }
+
+  // With load-dependent delays, every input pin loads its net, and stronger FETs charge a load faster.
+  let mut drive_strength: Vec<f64> = vec![0.0; net_capacitance.len()];
+  if options.femtofarads_per_fanout > 0 {
+    for component in components {
+      for net in component.input_nets() {
+        net_capacitance[net as usize] += options.femtofarads_per_fanout;
+      }
+      let (drain, size) = match component {
+        Component::Fet{ drain, size, .. } => (*drain, *size),
+        Component::FaultyFet(fet) => (fet.drain, fet.size),
+        _ => continue,
+      };
+      drive_strength[drain as usize] = drive_strength[drain as usize].max(size.strength());
+    }
+  }
+  // Heavily loaded nets take longer to settle: a net coming out of Invalid must be driven the same way
+  // for this many extra ticks before it resolves to Low or High. Nets no FET drives count as driven by a plain FET.
+  let mut settle_ticks: Vec<u32> = net_capacitance.iter().zip(&drive_strength).map(|(&c, &strength)| match strength > 0.0 {
+    true => (c as f64 / strength) as u32 / options.femtofarads_per_tick,
+    false => c / options.femtofarads_per_tick,
+  }).collect();
+  // Rails and other stimulus are ideal sources, which drive any load instantly. Otherwise a capacitor to gnd
+  // would hold gnd Invalid, and every FET sourced from it off, until it settled.
+  for component in components {
+    if let Component::Signal{ .. } | Component::EdgeSignal{ .. } | Component::Lfsr(_) = component {
+      for net in component.nets() {
+        settle_ticks[net as usize] = 0;
+      }
+    }
   }
+  settle_ticks
 }
 
+// Strongly drives each net to the corresponding bit of word, leaving Invalid bits undriven.
+fn drive_bus(drives: &mut [DriveType], nets: &[Net], word: &[NetState]) {
+  for (net, bit) in nets.iter().zip(word) {
+    let net = *net as usize;
+    match bit {
+      NetState::Low => drives[net] = merge_drives(drives[net], DriveType::Low),
+      NetState::High => drives[net] = merge_drives(drives[net], DriveType::High),
+      _ => (),
+    }
+  }
+}
+
+// Weak drives lose to any strong drive, like a pull resistor, and Unknown injects an X.
 fn parse_pattern_var(x: &u32) -> DriveType {
   match *x {
     0 => DriveType::Low,
     1 => DriveType::High,
     2 => DriveType::HighZ,
+    3 => DriveType::WeakLow,
+    4 => DriveType::WeakHigh,
+    5 => DriveType::Unknown,
     _ => panic!("Invalid pattern var: {}", *x),
   }
 }
@@ -91,236 +610,640 @@ pub fn init_panic_hook() {
   console_error_panic_hook::set_once();
 }
 
+/// Enables folding FET inverters, NANDs and NORs into single cells before simulating, which is on by default.
+/// Traces are identical either way; this is only here to compare against the plain FET simulation.
+#[wasm_bindgen]
+pub fn set_gate_abstraction(enabled: bool) {
+  GLOBAL_OPTIONS.lock().unwrap().abstract_gates = enabled;
+}
+
+/// Sets how much capacitance on a net costs one extra tick of settling time. See the capacitance opcodes.
+#[wasm_bindgen]
+pub fn set_femtofarads_per_tick(femtofarads: u32) {
+  GLOBAL_OPTIONS.lock().unwrap().femtofarads_per_tick = femtofarads.max(1);
+}
+
+/// Enables load-dependent delays: every input pin on a net (FET gates, and the inputs of gates, flip-flops, latches,
+/// SRAMs and devices) adds this much capacitance to it, and a net's capacitance is divided by the strength (W/L) of
+/// the strongest FET driving it before working out its settling time. So widely fanned out nets are slow, and bigger
+/// FETs drive them faster. Zero, the default, turns this off.
+#[wasm_bindgen]
+pub fn set_femtofarads_per_fanout(femtofarads: u32) {
+  GLOBAL_OPTIONS.lock().unwrap().femtofarads_per_fanout = femtofarads;
+}
+
+/// Enables recording every SRAM access during subsequent simulations, retrieved with get_sram_access_log.
+#[wasm_bindgen]
+pub fn set_sram_access_logging(enabled: bool) {
+  GLOBAL_OPTIONS.lock().unwrap().log_sram_accesses = enabled;
+}
+
+/// Enables reporting SRAM writes with an Invalid address or Invalid data bits as warnings, retrieved with get_warnings.
+/// A bad write held for many ticks is reported once, and again only if its address or data changes.
+#[wasm_bindgen]
+pub fn set_sram_strict_mode(enabled: bool) {
+  GLOBAL_OPTIONS.lock().unwrap().strict_sram = enabled;
+}
+
+/// When enabled, an SRAM write with an Invalid address corrupts every word it might have hit to Invalid,
+/// rather than being ignored. With every address bit Invalid that's the whole memory.
+#[wasm_bindgen]
+pub fn set_sram_pessimistic_mode(enabled: bool) {
+  GLOBAL_OPTIONS.lock().unwrap().pessimistic_sram = enabled;
+}
+
+/// Plugs a JS device into every subsequent simulation, after the description's own components. device is any object
+/// with a step(t, inputs) method, where inputs is a Uint8Array holding the state of each input net (as in traces),
+/// returning an array of one drive per output net, encoded as pattern vars: 0 Low, 1 High, 2 HighZ, 3 WeakLow,
+/// 4 WeakHigh, 5 Unknown.
+#[wasm_bindgen]
+pub fn add_device(inputs: &[u32], outputs: &[u32], device: device::JsDevice) {
+  let device = device::ScriptedDevice::new(inputs.to_vec(), outputs.to_vec(), device);
+  SCRIPTED_DEVICES.with(|devices| devices.borrow_mut().push(device));
+}
+
+/// Sets the faults to inject into every subsequent simulation, four words per fault. See Fault::parse_list.
+/// Pass an empty list to simulate the circuit as described again.
+#[wasm_bindgen]
+pub fn set_faults(faults: &[u32]) {
+  *GLOBAL_FAULTS.lock().unwrap() = Fault::parse_list(faults);
+}
+
+/// Removes every device added with add_device.
+#[wasm_bindgen]
+pub fn clear_devices() {
+  SCRIPTED_DEVICES.with(|devices| devices.borrow_mut().clear());
+}
+
 // Performance optimizations to perform:
 //   1. Replace this merge_drives function with a proper thing that uses flags then merges them up at the end.
 //   2. Maybe have a dirty list, so I don't resimulate everything like every time.
 
-#[wasm_bindgen]
-pub fn perform_simulation(
-  description: &[u32],
-  nets_to_trace: &[u32],
-  net_count: u32,
-  duration: u32,
-  clock_divider: u32,
-) -> Vec<u8> {
-  // We now unpack everything.
-  let mut first_shoot_through: i32 = -1;
-  let mut net_states: Vec<NetState> = vec![NetState::Invalid; net_count as usize];
-  let mut traces: Vec<Vec<NetState>> = Vec::new();
-  for _ in 0..nets_to_trace.len() {
-    traces.push(vec![NetState::Invalid]);
-  }
-  //let mut children: Vec<Vec<Net>> = vec![Vec::new(); net_count as usize];
-  //let mut components_by_output_net: Vec<Vec<Component>> =
-  //  (0..net_count).into_iter().map(|x| Vec::new()).collect();
-  let mut components: Vec<Component> = Vec::new();
-  let mut dirty_bitmap: Vec<u64> = Vec::new();
-
-  {
-    let mut i: usize = 0;
-    while i < description.len() {
-      match description[i] {
-        1 => {
-          let is_pfet = description[i + 1] == 1;
-          let gate = description[i + 2];
-          let drain = description[i + 3];
-          let source = description[i + 4];
-          //components_by_output_net[drain as usize].push(Component::Fet{
-          components.push(Component::Fet{is_pfet, gate, drain, source});
-          //children[gate as usize].push(drain);
-          //children[source as usize].push(drain);
-          i += 5;
-        }
-        2 => {
-          let net = description[i + 1];
-          let repeat = description[i + 2] != 0;
-          let length = description[i + 3];
-          let pattern = description[i + 4 .. i + 4 + length as usize].iter().map(parse_pattern_var).collect();
-          //components_by_output_net[net as usize].push(Component::Signal{pattern, repeat, net});
-          components.push(Component::Signal{pattern, repeat, net});
-          i += 4 + length as usize;
+/// A simulation set up from a description, for native Rust users. It starts with the same default options as the
+/// wasm API, changed with the with_* methods, which match the set_* functions. Nets are numbered as in the
+/// description, like the arguments to perform_simulation.
+pub struct Simulator {
+  options: SimulationOptions,
+  netlist: netlist::Netlist,
+  // Stuck-at and bridge faults, over canonical nets. FET faults are applied to the components straight away.
+  net_faults: Vec<Fault>,
+}
+
+/// Everything perform_simulation would store for the get_* functions, in native form.
+pub struct SimulationResults {
+  /// One trace per traced net: its state at the start of each tick, starting from an Invalid tick 0.
+  pub traces: Vec<Vec<NetState>>,
+  pub first_shoot_through: Option<u32>,
+  /// Laid out as for get_sram_contents.
+  pub sram_contents: Vec<u32>,
+  /// Laid out as for get_sram_access_log.
+  pub sram_access_log: Vec<u32>,
+  /// Laid out as for get_warnings.
+  pub warnings: Vec<u32>,
+  pub net_names: Vec<String>,
+  pub canonical_nets: Vec<Net>,
+  /// Bytes printed to consoles: everything from the first console in the description, then the next, and so on.
+  pub console_output: Vec<u8>,
+  /// One per display, in description order.
+  pub framebuffers: Vec<Framebuffer>,
+}
+
+impl Simulator {
+  pub fn new(description: &[u32], net_count: u32) -> Simulator {
+    Simulator::with_options(description, net_count, SimulationOptions::DEFAULT)
+  }
+
+  // The wasm API's simulators, which use the options from the set_* functions.
+  fn from_global_options(description: &[u32], net_count: u32) -> Simulator {
+    Simulator::with_options(description, net_count, *GLOBAL_OPTIONS.lock().unwrap())
+  }
+
+  fn with_options(description: &[u32], net_count: u32, options: SimulationOptions) -> Simulator {
+    Simulator { options, netlist: netlist::Netlist::parse(description, net_count), net_faults: Vec::new() }
+  }
+
+  /// See set_gate_abstraction.
+  pub fn with_gate_abstraction(mut self, enabled: bool) -> Simulator {
+    self.options.abstract_gates = enabled;
+    self
+  }
+
+  /// See set_femtofarads_per_tick.
+  pub fn with_femtofarads_per_tick(mut self, femtofarads: u32) -> Simulator {
+    self.options.femtofarads_per_tick = femtofarads.max(1);
+    self
+  }
+
+  /// See set_femtofarads_per_fanout.
+  pub fn with_femtofarads_per_fanout(mut self, femtofarads: u32) -> Simulator {
+    self.options.femtofarads_per_fanout = femtofarads;
+    self
+  }
+
+  /// See set_sram_access_logging.
+  pub fn with_sram_access_logging(mut self, enabled: bool) -> Simulator {
+    self.options.log_sram_accesses = enabled;
+    self
+  }
+
+  /// See set_sram_strict_mode.
+  pub fn with_sram_strict_mode(mut self, enabled: bool) -> Simulator {
+    self.options.strict_sram = enabled;
+    self
+  }
+
+  /// See set_sram_pessimistic_mode.
+  pub fn with_sram_pessimistic_mode(mut self, enabled: bool) -> Simulator {
+    self.options.pessimistic_sram = enabled;
+    self
+  }
+
+  /// The number of nets after flattening subcircuits, which may be more than the description was given.
+  pub fn net_count(&self) -> u32 {
+    self.netlist.net_count()
+  }
+
+  /// Adds a device, which steps after every component from the description. Devices are numbered in the order
+  /// they're added, after any built into the description, for DeviceWarnings.
+  pub fn add_device(&mut self, device: Box<dyn Device>) {
+    let mut device = device::PluggedDevice::new(device);
+    let canonical = &self.netlist.canonical;
+    device.map_nets(&|net| canonical[net as usize]);
+    self.netlist.components.push(Component::Device(device));
+  }
+
+  /// Injects a fault, which is simulated exactly like the faulty circuit would be.
+  pub fn inject_fault(&mut self, fault: Fault) {
+    let canonical = &self.netlist.canonical;
+    match fault {
+      Fault::StuckAt { net, level, from } =>
+        self.net_faults.push(Fault::StuckAt { net: canonical[net as usize], level, from }),
+      Fault::Bridge { net_a, net_b, from } =>
+        self.net_faults.push(Fault::Bridge { net_a: canonical[net_a as usize], net_b: canonical[net_b as usize], from }),
+      Fault::FetStuck { fet, closed, from } => {
+        let component = self.netlist.components.iter_mut()
+          .filter(|component| matches!(component, Component::Fet{ .. } | Component::FaultyFet(_)))
+          .nth(fet as usize)
+          .unwrap_or_else(|| panic!("No FET number {} to inject a fault into", fet));
+        if let Component::Fet{ is_pfet, gate, drain, source, size } = *component {
+          *component = Component::FaultyFet(fault::FaultyFet::new(is_pfet, gate, drain, source, size, closed, from));
         }
-        3 => {
-          let is_pull_down = description[i + 1] == 1;
-          let net = description[i + 2];
-          components.push(Component::PullResistor{is_pull_down, net});
-          i += 3;
+      }
+    }
+  }
+
+  /// Every single stuck fault worth grading: stuck-at-0 and stuck-at-1 on each canonical net something connects to,
+  /// then each FET stuck open and stuck closed, all active from tick 0. Rails are left out: a net held at a constant
+  /// level by a signal and never used as a FET gate can't be stuck at that level in any way that matters.
+  pub fn single_stuck_faults(&self) -> Vec<Fault> {
+    let canonical = &self.netlist.canonical;
+    let mut connected = vec![false; canonical.len()];
+    let mut gates = vec![false; canonical.len()];
+    let mut constant: Vec<Option<bool>> = vec![None; canonical.len()];
+    let mut fet_count: u32 = 0;
+    for component in &self.netlist.components {
+      for net in component.nets() {
+        connected[net as usize] = true;
+      }
+      match component {
+        Component::Fet{ gate, .. } => {
+          gates[*gate as usize] = true;
+          fet_count += 1;
         }
-        4 => {
-          let address_bit_count = description[i + 1];
-          let word_size = description[i + 2];
-          let write_enable_net = description[i + 3];
-          let contents_length = description[i + 4];
-          let mut idx = i + 5;
-          let mut contents: Vec<NetState> = vec![];
-          for num in description[idx .. idx + contents_length as usize].iter() {
-            for bit_position in 0..word_size {
-              contents.push(
-                if (num >> bit_position) & 1 != 0 {
-                  NetState::High
-                } else {
-                  NetState::Low
-                }
-              );
-            }
-          }
-          while contents.len() < (1 << address_bit_count) * word_size as usize {
-            contents.push(NetState::Invalid);
-          }
-          //let contents = description[idx .. idx + contents_length as usize].iter().cloned().collect();
-          idx += contents_length as usize;
-          let address_nets = description[idx .. idx + address_bit_count as usize].iter().cloned().collect();
-          idx += address_bit_count as usize;
-          let bus_in_nets = description[idx .. idx + word_size as usize].iter().cloned().collect();
-          idx += word_size as usize;
-          let bus_out_nets = description[idx .. idx + word_size as usize].iter().cloned().collect();
-          idx += word_size as usize;
-          components.push(Component::Sram{
-            address_bit_count,
-            word_size,
-            contents,
-            address_nets,
-            bus_in_nets,
-            bus_out_nets,
-            write_enable_net,
-          });
-          i = idx;
+        Component::FaultyFet(_) => fet_count += 1,
+        Component::Signal{ pattern, net, timing, .. } if timing.as_ref().is_none_or(|timing| timing.duty.is_none()) => {
+          constant[*net as usize] = match pattern[0] {
+            DriveType::Low if pattern.iter().all(|x| matches!(x, DriveType::Low)) => Some(false),
+            DriveType::High if pattern.iter().all(|x| matches!(x, DriveType::High)) => Some(true),
+            _ => None,
+          };
         }
-        _ => panic!("Deserialization failure. Hit: {} at position {} out of length {}", description[i], i, description.len()),
+        _ => (),
       }
-      if i >= description.len() {
-        panic!("Deserialization failure. Fell off end where there should be a sentinel.");
+    }
+    let mut faults: Vec<Fault> = Vec::new();
+    for net in 0..canonical.len() {
+      if canonical[net] as usize != net || !connected[net] {
+        continue;
       }
-      if description[i] != 123456789 {
-        panic!("Expected sentinel. Hit: {} at position {} out of length {}", description[i], i, description.len());
+      for level in [false, true] {
+        if gates[net] || constant[net] != Some(level) {
+          faults.push(Fault::StuckAt { net: net as Net, level, from: 0 });
+        }
       }
-      i += 1;
     }
+    for fet in 0..fet_count {
+      for closed in [false, true] {
+        faults.push(Fault::FetStuck { fet, closed, from: 0 });
+      }
+    }
+    faults
   }
 
-  let mut drives = vec![DriveType::HighZ; net_count as usize];
-
-  for t in 0..duration {
-    let now = (t / clock_divider) as usize;
-
-    for net in 0..net_count {
-      drives[net as usize] = DriveType::HighZ;
+  pub fn run(self, nets_to_trace: &[u32], duration: u32, clock_divider: u32) -> SimulationResults {
+    let options = self.options;
+    let netlist = self.netlist;
+    let mut stuck_at: Vec<Option<(NetState, u32)>> = vec![None; netlist.net_count() as usize];
+    let mut bridges: Vec<(Net, Net, u32)> = Vec::new();
+    for fault in &self.net_faults {
+      match *fault {
+        Fault::StuckAt { net, level, from } => {
+          let state = match level {
+            false => NetState::Low,
+            true => NetState::High,
+          };
+          stuck_at[net as usize] = Some((state, from));
+        }
+        Fault::Bridge { net_a, net_b, from } => bridges.push((net_a, net_b, from)),
+        Fault::FetStuck { .. } => (),
+      }
+    }
+    // Tracing a net that was wired to others traces the net they were all merged into.
+    let nets_to_trace: Vec<Net> = nets_to_trace.iter().map(|&net| netlist.canonical[net as usize]).collect();
+    let net_count = netlist.net_count();
+    let mut components = netlist.components;
+    let mut first_shoot_through: Option<u32> = None;
+    let mut net_states: Vec<NetState> = vec![NetState::Invalid; net_count as usize];
+    let mut traces: Vec<Vec<NetState>> = Vec::new();
+    for _ in 0..nets_to_trace.len() {
+      traces.push(vec![NetState::Invalid]);
     }
+    let mut sram_access_log: Vec<u32> = Vec::new();
+    let mut warnings: Vec<u32> = Vec::new();
 
-    for component in &mut components {
-      match component {
-        Component::Fet{is_pfet, gate, drain, source} => {
-          let gate_state   = net_states[*gate   as usize];
-          let drain_state  = net_states[*drain  as usize];
-          let source_state = net_states[*source as usize];
-          match (*is_pfet, gate_state, source_state) {
-            // Normal operation of nfets and pfets.
-            (false, NetState::High, NetState::Low) =>
-              drives[*drain as usize] = merge_drives(drives[*drain as usize], DriveType::Low),
-            (true, NetState::Low, NetState::High) =>
-              drives[*drain as usize] = merge_drives(drives[*drain as usize], DriveType::High),
-            // In all other cases we don't drive.
-            _ => (),
+    // Only pay for resolving fights between FETs by strength if any two FETs differ in strength.
+    let mut fet_strengths = components.iter().filter_map(|component| match component {
+      Component::Fet{ size, .. } => Some(size.strength()),
+      Component::FaultyFet(fet) => Some(fet.size.strength()),
+      _ => None,
+    });
+    let first_strength = fet_strengths.next();
+    let mut fet_strengths: Vec<(f64, f64)> = match fet_strengths.any(|strength| Some(strength) != first_strength) {
+      true => vec![(0.0, 0.0); net_count as usize],
+      false => Vec::new(),
+    };
+    // Every resistor, and for each net whether resistors are pulling it (low, high) this tick. Both empty if there
+    // are no resistors.
+    let resistors: Vec<(Net, Net)> = components.iter().filter_map(|component| match component {
+      Component::Resistor{ net_a, net_b } => Some((*net_a, *net_b)),
+      _ => None,
+    }).collect();
+    let mut resistor_pulls: Vec<(bool, bool)> = match resistors.is_empty() {
+      false => vec![(false, false); net_count as usize],
+      true => Vec::new(),
+    };
+
+    let settle_ticks = settle_ticks(&components, netlist.net_capacitance, options);
+    if options.abstract_gates {
+      let mut observable: Vec<bool> = settle_ticks.iter().map(|&ticks| ticks > 0).collect();
+      for &net in &nets_to_trace {
+        observable[net as usize] = true;
+      }
+      // Faulty nets behave unlike anything a cell could model.
+      for fault in &self.net_faults {
+        match *fault {
+          Fault::StuckAt { net, .. } => observable[net as usize] = true,
+          Fault::Bridge { net_a, net_b, .. } => {
+            observable[net_a as usize] = true;
+            observable[net_b as usize] = true;
           }
+          Fault::FetStuck { .. } => (),
         }
-        Component::PullResistor{ is_pull_down, net } =>
-          drives[*net as usize] = merge_drives(drives[*net as usize], match is_pull_down {
-            true => DriveType ::WeakLow,
-            false => DriveType::WeakHigh,
-          }),
-        Component::Signal{ pattern, repeat, net } => {
-          let signal_output = pattern[match repeat {
-            true => now % pattern.len(),
-            false => std::cmp::min(now, pattern.len() - 1),
-          }];
-          drives[*net as usize] = merge_drives(drives[*net as usize], signal_output);
-        }
-        Component::Sram{
-          address_bit_count,
-          word_size,
-          contents,
-          address_nets,
-          bus_in_nets,
-          bus_out_nets,
-          write_enable_net,
-        } => {
-          // Figure out the address.
-          let mut address_valid = true;
-          let mut address: u32 = 0;
-          for addr_net in address_nets.iter().rev() {
-            address <<= 1;
-            match net_states[*addr_net as usize] {
-              NetState::Low => (),
-              NetState::High => address |= 1,
-              _ => address_valid = false,
+      }
+      abstraction::abstract_gates(&mut components, &observable);
+    }
+
+    let mut settle_progress: Vec<u32> = vec![0; net_count as usize];
+    let mut settle_target: Vec<NetState> = vec![NetState::Invalid; net_count as usize];
+
+    let mut drives = vec![DriveType::HighZ; net_count as usize];
+
+    for t in 0..duration {
+      let now = (t / clock_divider) as usize;
+
+      for net in 0..net_count {
+        drives[net as usize] = DriveType::HighZ;
+      }
+
+      let mut sram_index: u32 = 0;
+      let mut sequential_index: u32 = 0;
+      let mut device_index: u32 = 0;
+      for component in &mut components {
+        match component {
+          Component::Fet{is_pfet, gate, drain, source, size} => {
+            let gate_state   = net_states[*gate   as usize];
+            let source_state = net_states[*source as usize];
+            match (*is_pfet, gate_state, source_state) {
+              // Normal operation of nfets and pfets.
+              (false, NetState::High, NetState::Low) =>
+                drive_from_fet(&mut drives, &mut fet_strengths, *drain, DriveType::Low, *size),
+              (true, NetState::Low, NetState::High) =>
+                drive_from_fet(&mut drives, &mut fet_strengths, *drain, DriveType::High, *size),
+              // In all other cases we don't drive.
+              _ => (),
             }
           }
-          if address_valid {
-            let base_address = (address * *word_size) as usize;
-            match net_states[*write_enable_net as usize] {
-              NetState::Low => {
-                // Read mode: Drive bus_out_nets with the read value.
-                for i in 0..*word_size {
-                  let bus_out_net = bus_out_nets[i as usize] as usize;
-                  match contents[base_address + i as usize] {
-                    NetState::Low => drives[bus_out_net] = merge_drives(drives[bus_out_net], DriveType::Low),
-                    NetState::High => drives[bus_out_net] = merge_drives(drives[bus_out_net], DriveType::High),
-                    _ => (),
+          Component::PullResistor{ is_pull_down, net } =>
+            drives[*net as usize] = merge_drives(drives[*net as usize], match is_pull_down {
+              true => DriveType ::WeakLow,
+              false => DriveType::WeakHigh,
+            }),
+          // Resistors only pull once every other drive is known, below.
+          Component::Resistor{ .. } => (),
+          // Capacitors only add load, which was already folded into settle_ticks.
+          Component::Capacitor{ .. } => (),
+          Component::Gate(gate) => {
+            let output = gate.output as usize;
+            drives[output] = merge_drives(drives[output], gate.step(&net_states));
+          }
+          Component::Sequential(element) => {
+            let q = element.q as usize;
+            drives[q] = merge_drives(drives[q], element.step(t, &net_states, sequential_index, &mut warnings));
+            sequential_index += 1;
+          }
+          Component::Cell(cell) => {
+            let output = cell.output as usize;
+            drives[output] = merge_drives(drives[output], cell.step(&net_states));
+          }
+          Component::FaultyFet(fet) =>
+            drive_from_fet(&mut drives, &mut fet_strengths, fet.drain, fet.step(t, &net_states), fet.size),
+          Component::Device(device) => {
+            device.step(t, &net_states, &mut drives, device_index, &mut warnings);
+            device_index += 1;
+          }
+          Component::Signal{ pattern, repeat, net, timing } => {
+            let signal_output = signal_output(pattern, *repeat, timing.as_ref(), t, now);
+            drives[*net as usize] = merge_drives(drives[*net as usize], signal_output);
+          }
+          Component::Lfsr(lfsr) => lfsr.step(t, &mut drives),
+          Component::HandshakeResponder(responder) => {
+            let ack = responder.ack as usize;
+            drives[ack] = merge_drives(drives[ack], responder.step(t, &net_states));
+          }
+          Component::TriggeredPulse(pulse) => {
+            let out = pulse.out as usize;
+            drives[out] = merge_drives(drives[out], pulse.step(t, &net_states));
+          }
+          Component::BusMemory(memory) => memory.step(t, &net_states, &mut drives),
+          Component::EdgeSignal{ edges, net } => {
+            let signal_output = edge_signal_output(edges, t);
+            drives[*net as usize] = merge_drives(drives[*net as usize], signal_output);
+          }
+          Component::Sram{
+            word_size,
+            contents,
+            address_nets,
+            bus_in_nets,
+            bus_out_nets,
+            write_enable_net,
+            last_access,
+            reported_bad_write,
+            timing,
+            ..
+          } => {
+            // Figure out the address.
+            let (address, address_invalid_mask) = pack_word(address_nets.iter().map(|net| &net_states[*net as usize]));
+            let address_valid = address_invalid_mask == 0;
+            if let Some(timing) = timing {
+              let data = pack_word(bus_in_nets.iter().map(|net| &net_states[*net as usize]));
+              timing.check_write_timing(
+                t,
+                (address, address_invalid_mask),
+                data,
+                net_states[*write_enable_net as usize],
+                sram_index,
+                &mut warnings,
+              );
+            }
+            let mut read_range = None;
+            // In strict mode, the bad write this tick, with the kind and detail of the warning it gets.
+            let mut bad_write: Option<(BadWrite, WarningKind, u32)> = None;
+            if !address_valid {
+              if let NetState::High = net_states[*write_enable_net as usize] {
+                if options.strict_sram {
+                  let write = BadWrite { address, address_invalid_mask, data: 0, data_invalid_mask: 0 };
+                  bad_write = Some((write, WarningKind::SramWriteInvalidAddress, address_invalid_mask));
+                }
+                if options.pessimistic_sram {
+                  // We don't know which word got written, so every word the address might have been now holds garbage.
+                  for (word_address, word) in contents.chunks_mut(*word_size as usize).enumerate() {
+                    if word_address as u32 & !address_invalid_mask == address {
+                      for bit in word {
+                        *bit = NetState::Invalid;
+                      }
+                    }
                   }
                 }
               }
-              NetState::High => {
-                // Write mode: Perform the write.
-                for i in 0..*word_size {
-                  contents[base_address + i as usize] = net_states[bus_in_nets[i as usize] as usize];
+            }
+            if address_valid {
+              let base_address = (address * *word_size) as usize;
+              let word_range = base_address .. base_address + *word_size as usize;
+              match net_states[*write_enable_net as usize] {
+                // Read mode: bus_out_nets get driven with the read value below.
+                NetState::Low => read_range = Some(word_range.clone()),
+                NetState::High => {
+                  // Write mode: Perform the write.
+                  for i in 0..*word_size {
+                    contents[base_address + i as usize] = net_states[bus_in_nets[i as usize] as usize];
+                  }
+                  if options.strict_sram {
+                    let (data, data_invalid_mask) = pack_word(contents[word_range.clone()].iter());
+                    if data_invalid_mask != 0 {
+                      let write = BadWrite { address, address_invalid_mask: 0, data, data_invalid_mask };
+                      bad_write = Some((write, WarningKind::SramWriteInvalidData, address));
+                    }
+                  }
                 }
+                _ => (),
+              }
+              if options.log_sram_accesses {
+                let is_write = match net_states[*write_enable_net as usize] {
+                  NetState::Low => Some(false),
+                  NetState::High => Some(true),
+                  _ => None,
+                };
+                if let Some(is_write) = is_write {
+                  // After a write the word holds exactly what was written, so either way we log the stored word.
+                  let (value, invalid_mask) = pack_word(contents[word_range].iter());
+                  let access = SramAccess { address, is_write, value, invalid_mask };
+                  // Holding the same access for many ticks only gets logged once.
+                  if *last_access != Some(access) {
+                    sram_access_log.extend_from_slice(&[t, sram_index, address, is_write as u32, value, invalid_mask]);
+                    *last_access = Some(access);
+                  }
+                }
+              }
+            }
+            // Holding the same bad write for many ticks only gets warned about once.
+            if let Some((write, kind, detail)) = bad_write {
+              if *reported_bad_write != Some(write) {
+                warnings.extend_from_slice(&[t, kind as u32, sram_index, detail]);
               }
-              _ => (),
             }
+            *reported_bad_write = bad_write.map(|(write, ..)| write);
+            match timing {
+              // Reads take read_latency ticks to show up on the bus.
+              Some(timing) if timing.read_latency > 0 => {
+                timing.read_pipeline.push_back(read_range.map(|range| contents[range].to_vec()));
+                if timing.read_pipeline.len() > timing.read_latency as usize {
+                  if let Some(Some(word)) = timing.read_pipeline.pop_front() {
+                    drive_bus(&mut drives, bus_out_nets, &word);
+                  }
+                }
+              }
+              _ => if let Some(range) = read_range {
+                drive_bus(&mut drives, bus_out_nets, &contents[range]);
+              },
+            }
+            sram_index += 1;
           }
         }
       }
+
+      for (net, strengths) in fet_strengths.iter_mut().enumerate() {
+        let (low_strength, high_strength) = std::mem::take(strengths);
+        drives[net] = merge_drives(drives[net], fet_contention(low_strength, high_strength));
+      }
+      if !resistors.is_empty() {
+        resistor_pulls.fill((false, false));
+        spread_resistor_pulls(&resistors, &mut resistor_pulls, |net, pulls| match drives[net] {
+          DriveType::HighZ => pulls,
+          _ => (net_states[net] == NetState::Low, net_states[net] == NetState::High),
+        }, |a, b| (a.0 || b.0, a.1 || b.1));
+        for (net, pulls) in resistor_pulls.iter().enumerate() {
+          if let DriveType::HighZ = drives[net] {
+            drives[net] = resistive_drive(*pulls);
+          }
+        }
+      }
+
+      // Bridged nets each see both of their drives.
+      for &(net_a, net_b, from) in &bridges {
+        if t >= from {
+          let (net_a, net_b) = (net_a as usize, net_b as usize);
+          let drive = merge_drives(drives[net_a], drives[net_b]);
+          drives[net_a] = drive;
+          drives[net_b] = drive;
+        }
+      }
+
+      // Produce new values.
+      for net in 0..net_count {
+        //let last = streams[net][t as usize];
+        let last = net_states[net as usize];
+        let mut new_state = match (last, drives[net as usize]) {
+          (NetState::ShootThrough, _) => NetState::ShootThrough,
+          (_, DriveType::ShootThrough) => NetState::ShootThrough,
+          (_, DriveType::HighZ) |
+          (_, DriveType::Unknown) |
+          (NetState::Low, DriveType::High) |
+          (NetState::Low, DriveType::WeakHigh) |
+          (NetState::High, DriveType::Low) |
+          (NetState::High, DriveType::WeakLow)
+            => NetState::Invalid,
+          (NetState::Invalid, DriveType::Low) => NetState::Low,
+          (NetState::Invalid, DriveType::High) => NetState::High,
+          (NetState::Invalid, DriveType::WeakLow) => NetState::Low,
+          (NetState::Invalid, DriveType::WeakHigh) => NetState::High,
+          _ => last,
+        };
+        if settle_ticks[net as usize] > 0 {
+          let net = net as usize;
+          match (last, new_state) {
+            (NetState::Invalid, NetState::Low) | (NetState::Invalid, NetState::High) => {
+              if settle_target[net] != new_state {
+                settle_target[net] = new_state;
+                settle_progress[net] = 0;
+              }
+              if settle_progress[net] < settle_ticks[net] {
+                settle_progress[net] += 1;
+                new_state = NetState::Invalid;
+              }
+            }
+            _ => settle_target[net] = NetState::Invalid,
+          }
+        }
+        if let Some((state, from)) = stuck_at[net as usize] {
+          if t >= from {
+            new_state = state;
+          }
+        }
+        net_states[net as usize] = new_state;
+        if first_shoot_through.is_none() {
+          if let NetState::ShootThrough = new_state {
+            first_shoot_through = Some(t);
+          }
+        }
+      }
+
+      // Save the ones that are being probed.
+      for (i, &net) in nets_to_trace.iter().enumerate() {
+        traces[i].push(net_states[net as usize]);
+      }
     }
 
-    // Produce new values.
-    for net in 0..net_count {
-      //let last = streams[net][t as usize];
-      let last = net_states[net as usize];
-      let new_state = match (last, drives[net as usize]) {
-        (NetState::ShootThrough, _) => NetState::ShootThrough,
-        (_, DriveType::ShootThrough) => NetState::ShootThrough,
-        (_, DriveType::HighZ) |
-        (NetState::Low, DriveType::High) |
-        (NetState::Low, DriveType::WeakHigh) |
-        (NetState::High, DriveType::Low) |
-        (NetState::High, DriveType::WeakLow)
-          => NetState::Invalid,
-        (NetState::Invalid, DriveType::Low) => NetState::Low,
-        (NetState::Invalid, DriveType::High) => NetState::High,
-        (NetState::Invalid, DriveType::WeakLow) => NetState::Low,
-        (NetState::Invalid, DriveType::WeakHigh) => NetState::High,
-        _ => last,
+    // Dump the final contents of every SRAM and bus memory, in the order they appear in the description.
+    let mut sram_contents: Vec<u32> = Vec::new();
+    for component in &components {
+      let (address_bit_count, word_size, contents) = match component {
+        Component::Sram{ address_bit_count, word_size, contents, .. } => (*address_bit_count, *word_size, contents),
+        Component::BusMemory(memory) => (memory.address_bit_count(), memory.word_size(), &memory.contents),
+        _ => continue,
       };
-      net_states[net as usize] = new_state;
-      if first_shoot_through == -1 {
-        match new_state {
-          NetState::ShootThrough => first_shoot_through = t as i32,
-          _ => (),
-        }
+      sram_contents.push(word_size);
+      sram_contents.push(1 << address_bit_count);
+      for word in contents.chunks(word_size as usize).take(1 << address_bit_count) {
+        let (value, invalid_mask) = pack_word(word.iter());
+        sram_contents.push(value);
+        sram_contents.push(invalid_mask);
       }
     }
 
-    // Save the ones that are being probed.
-    for (i, &net) in nets_to_trace.iter().enumerate() {
-      traces[i].push(net_states[net as usize]);
+    let mut results = SimulationResults {
+      traces,
+      first_shoot_through,
+      sram_contents,
+      sram_access_log,
+      warnings,
+      net_names: netlist.net_names,
+      canonical_nets: netlist.canonical,
+      console_output: Vec::new(),
+      framebuffers: Vec::new(),
+    };
+    for component in components.iter_mut() {
+      if let Component::Device(device) = component {
+        device.finish(&mut results);
+      }
     }
+    results
+  }
+}
+
+#[wasm_bindgen]
+pub fn perform_simulation(
+  description: &[u32],
+  nets_to_trace: &[u32],
+  net_count: u32,
+  duration: u32,
+  clock_divider: u32,
+) -> Vec<u8> {
+  let mut simulator = Simulator::from_global_options(description, net_count);
+  for fault in GLOBAL_FAULTS.lock().unwrap().iter() {
+    simulator.inject_fault(*fault);
   }
+  SCRIPTED_DEVICES.with(|devices| {
+    for device in devices.borrow().iter() {
+      simulator.add_device(Box::new(device.clone()));
+    }
+  });
+  let results = simulator.run(nets_to_trace, duration, clock_divider);
 
   let mut bytes: Vec<u8> = Vec::new();
   let mut indices: Vec<u32> = Vec::new();
 
   // Pack up our results.
-  for trace in traces {
+  for trace in results.traces {
     indices.push(bytes.len() as u32);
     indices.push(trace.len() as u32);
     for val in trace {
@@ -328,19 +1251,227 @@ pub fn perform_simulation(
     }
   }
 
-  unsafe { GLOBAL_INDICES = indices; }
-  unsafe { GLOBAL_FIRST_SHOOT_THROUGH = first_shoot_through; }
+  *GLOBAL_INDICES.lock().unwrap() = indices;
+  *GLOBAL_FIRST_SHOOT_THROUGH.lock().unwrap() = results.first_shoot_through.map_or(-1, |t| t as i32);
+  *GLOBAL_SRAM_CONTENTS.lock().unwrap() = results.sram_contents;
+  *GLOBAL_SRAM_ACCESS_LOG.lock().unwrap() = results.sram_access_log;
+  *GLOBAL_WARNINGS.lock().unwrap() = results.warnings;
+  *GLOBAL_NET_NAMES.lock().unwrap() = results.net_names;
+  *GLOBAL_CANONICAL_NETS.lock().unwrap() = results.canonical_nets;
+  *GLOBAL_CONSOLE_OUTPUT.lock().unwrap() = results.console_output;
+  *GLOBAL_FRAMEBUFFERS.lock().unwrap() = results.framebuffers;
   bytes
 }
 
+/// Grades a stimulus set by fault simulation over every single stuck fault (see Simulator::single_stuck_faults),
+/// with any devices from add_device but none of the faults from set_faults. Returns the percentage of faults
+/// detected on graded_nets at strobe_ticks (or at every tick if strobe_ticks is empty). See simulate_faults.
+#[wasm_bindgen]
+pub fn run_fault_simulation(
+  description: &[u32],
+  graded_nets: &[u32],
+  strobe_ticks: &[u32],
+  net_count: u32,
+  duration: u32,
+  clock_divider: u32,
+) -> f64 {
+  let make_simulator = || {
+    let mut simulator = Simulator::from_global_options(description, net_count);
+    SCRIPTED_DEVICES.with(|devices| {
+      for device in devices.borrow().iter() {
+        simulator.add_device(Box::new(device.clone()));
+      }
+    });
+    simulator
+  };
+  let faults = make_simulator().single_stuck_faults();
+  let coverage = simulate_faults(&make_simulator, &faults, graded_nets, strobe_ticks, duration, clock_divider);
+  *GLOBAL_UNDETECTED_FAULTS.lock().unwrap() = coverage.undetected.clone();
+  coverage.percentage()
+}
+
+/// Generates test vectors for a combinational netlist with inputs on input_nets and outputs on output_nets.
+/// See generate_tests. Returns one Signal pattern per input, each as long as the number of vectors, one after another.
+#[wasm_bindgen]
+pub fn generate_test_patterns(description: &[u32], input_nets: &[u32], output_nets: &[u32], net_count: u32) -> Vec<u32> {
+  let test_set = generate_tests(description, net_count, input_nets, output_nets);
+  *GLOBAL_UNDETECTED_FAULTS.lock().unwrap() = [test_set.redundant.clone(), test_set.aborted.clone()].concat();
+  test_set.patterns().concat()
+}
+
 #[wasm_bindgen]
 pub fn get_indices() -> Vec<u32> {
-  unsafe { GLOBAL_INDICES.clone() }
+  GLOBAL_INDICES.lock().unwrap().clone()
 }
 
 #[wasm_bindgen]
 pub fn get_first_shoot_through() -> i32 {
-  unsafe { GLOBAL_FIRST_SHOOT_THROUGH }
+  *GLOBAL_FIRST_SHOOT_THROUGH.lock().unwrap()
+}
+
+/// Final contents of every SRAM and bus memory from the last simulation, in description order.
+/// Each memory is laid out as: word_size, word_count, then word_count pairs of (value, invalid_mask),
+/// where invalid_mask has a one for every bit that was left Invalid (or ShootThrough).
+#[wasm_bindgen]
+pub fn get_sram_contents() -> Vec<u32> {
+  GLOBAL_SRAM_CONTENTS.lock().unwrap().clone()
+}
+
+/// SRAM accesses from the last simulation, if enabled with set_sram_access_logging.
+/// Each entry is six words: tick, sram_index, address, is_write, value, invalid_mask.
+/// An entry is only recorded when an SRAM's access changes, so a read held on one address for many ticks appears once.
+#[wasm_bindgen]
+pub fn get_sram_access_log() -> Vec<u32> {
+  GLOBAL_SRAM_ACCESS_LOG.lock().unwrap().clone()
+}
+
+/// Warnings from the last simulation, four words each: tick, kind, index, detail. See WarningKind.
+#[wasm_bindgen]
+pub fn get_warnings() -> Vec<u32> {
+  GLOBAL_WARNINGS.lock().unwrap().clone()
+}
+
+/// Names of every net in the last simulation, one per line, indexed by net.
+/// Nets inside subcircuit instances come after the top level's own nets, named by their instance path like
+/// "cpu/alu/adder3/carry", or by their local index like "cpu/alu/adder3/7" if the definition didn't name them.
+/// Top level nets that were never named are empty lines.
+#[wasm_bindgen]
+pub fn get_net_names() -> String {
+  GLOBAL_NET_NAMES.lock().unwrap().join("\n")
+}
+
+/// Everything printed to consoles in the last simulation, console by console in description order.
+/// Bytes that aren't valid UTF-8 come out as replacement characters.
+#[wasm_bindgen]
+pub fn get_console_output() -> String {
+  String::from_utf8_lossy(&GLOBAL_CONSOLE_OUTPUT.lock().unwrap()).into_owned()
+}
+
+/// The number of displays in the last simulation.
+#[wasm_bindgen]
+pub fn get_framebuffer_count() -> u32 {
+  GLOBAL_FRAMEBUFFERS.lock().unwrap().len() as u32
+}
+
+/// The image size of a display from the last simulation, in pixels, as [width, height].
+/// Seven-segment displays are drawn 8 by 11 pixels per digit.
+#[wasm_bindgen]
+pub fn get_framebuffer_size(index: u32) -> Vec<u32> {
+  let (width, height) = GLOBAL_FRAMEBUFFERS.lock().unwrap()[index as usize].image_size();
+  vec![width, height]
+}
+
+/// A display's image as it was at the start of tick t in the last simulation, as RGBA bytes row by row,
+/// ready for an ImageData. Pass the simulation's duration for the final image.
+#[wasm_bindgen]
+pub fn get_framebuffer_rgba(index: u32, t: u32) -> Vec<u8> {
+  GLOBAL_FRAMEBUFFERS.lock().unwrap()[index as usize].rgba_at(t)
+}
+
+/// For every net in the last simulation, the net it was merged into by wires, or itself if it's canonical.
+/// The canonical net of a wired group is its best named member (explicit names beat default instance net names,
+/// and either kind given closer to the top level wins), with ties going to the lowest index. Only canonical nets are simulated, but every alias can be traced.
+#[wasm_bindgen]
+pub fn get_canonical_nets() -> Vec<u32> {
+  GLOBAL_CANONICAL_NETS.lock().unwrap().clone()
+}
+
+/// The faults the last run_fault_simulation didn't detect, or the last generate_test_patterns found no test for, four words each as for set_faults.
+#[wasm_bindgen]
+pub fn get_undetected_faults() -> Vec<u32> {
+  GLOBAL_UNDETECTED_FAULTS.lock().unwrap().iter().flat_map(|fault| fault.to_words()).collect()
+}
+
+// Description builders and a seeded random number generator, shared by the test modules.
+#[cfg(test)]
+mod testing {
+  pub const SENTINEL: u32 = 123456789;
+  pub const VDD: u32 = 0;
+  pub const GND: u32 = 1;
+
+  pub fn signal(net: u32, pattern: &[u32], repeat: bool) -> Vec<u32> {
+    [&[2, net, repeat as u32, pattern.len() as u32][..], pattern, &[SENTINEL]].concat()
+  }
+
+  pub fn fet(is_pfet: bool, gate: u32, drain: u32, source: u32) -> Vec<u32> {
+    vec![1, is_pfet as u32, gate, drain, source, SENTINEL]
+  }
+
+  // Drives VDD high and GND low.
+  pub fn rails() -> Vec<u32> {
+    [signal(VDD, &[1], false), signal(GND, &[0], false)].concat()
+  }
+
+  // A textbook CMOS cell between the rails: kind 0 is an inverter, 1 a NAND and 2 a NOR, over one, two and three
+  // inputs. The internal nodes of series chains are allocated from next.
+  pub fn cell(kind: u32, inputs: &[u32], output: u32, next: &mut u32) -> Vec<u32> {
+    let mut description = Vec::new();
+    // Inverters are a series chain of one; NANDs have series pull-downs, NORs series pull-ups.
+    for (is_pfet, rail, series) in [(true, VDD, kind != 1), (false, GND, kind != 2)] {
+      let mut source = rail;
+      for (i, &input) in inputs.iter().enumerate() {
+        let drain = match !series || i == inputs.len() - 1 {
+          true => output,
+          false => {
+            *next += 1;
+            *next - 1
+          }
+        };
+        description.extend(fet(is_pfet, input, drain, match series { true => source, false => rail }));
+        source = drain;
+      }
+    }
+    description
+  }
+
+  // A xorshift generator with a fixed seed, so randomized tests are reproducible. Each call returns a number below n.
+  pub fn seeded_below() -> impl FnMut(u32) -> u32 {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    move |n: u32| {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      (state % n as u64) as u32
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::testing::{rails, signal, SENTINEL, VDD};
+  use super::{NetState, Simulator, WarningKind};
+
+  #[test]
+  fn strict_sram_warns_once_per_bad_write() {
+    // A one-bit SRAM with a write to an Invalid address, and then a write of Invalid data, each held for a while.
+    let mut description = vec![4, 1, 1, 2, 0, 3, 4, 5, SENTINEL];
+    description.extend(signal(2, &[0, 1, 1, 1, 1, 1, 0, 1, 1, 1, 1, 0], false));
+    description.extend(signal(3, &[2, 2, 2, 2, 2, 2, 0], false));
+    description.extend(signal(4, &[1, 1, 1, 1, 1, 1, 1, 2], false));
+    let warnings = Simulator::new(&description, 6).with_sram_strict_mode(true).run(&[], 16, 1).warnings;
+    let kinds: Vec<u32> = warnings.chunks(4).map(|warning| warning[1]).collect();
+    assert_eq!(kinds, [WarningKind::SramWriteInvalidAddress as u32, WarningKind::SramWriteInvalidData as u32]);
+  }
+
+  #[test]
+  fn resistors_relay_levels_but_cannot_hold_up_a_released_net() {
+    // Net 2 is driven High until tick 4 and then released, with a resistor to net 3 and one to itself.
+    // Nets 4 and 5 are a resistor chain hanging off VDD.
+    let mut description = rails();
+    description.extend([17, 2, 2, 0, 1, 4, 2, SENTINEL]);
+    description.extend([6, 2, 3, 1000, SENTINEL, 6, 2, 2, 1000, SENTINEL]);
+    description.extend([6, VDD, 4, 1000, SENTINEL, 6, 4, 5, 1000, SENTINEL]);
+    let traces = Simulator::new(&description, 6).run(&[2, 3, 5], 12, 1).traces;
+    let states = |trace: &str| -> Vec<NetState> {
+      trace.chars().map(|c| match c {
+        '1' => NetState::High,
+        _ => NetState::Invalid,
+      }).collect()
+    };
+    assert_eq!(traces[0], states("X1111XXXXXXXX"));
+    assert_eq!(traces[1], states("XX111XXXXXXXX"));
+    assert_eq!(traces[2], states("XX11111111111"));
+  }
 }
 
 /*
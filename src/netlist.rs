@@ -0,0 +1,596 @@
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use super::{device, display, gates, optional_net, parse_pattern_var, peripherals, queue, sequential, stimulus, testbench, Component, FetSize, Net, NetState, SignalTiming, SramTiming};
+
+// A subcircuit. Its body is a description of its own over local nets, the first port_count of which are its ports.
+struct Definition {
+  port_count: u32,
+  local_net_count: u32,
+  body: Rc<Vec<u32>>,
+}
+
+// A description with every subcircuit instance flattened out. Nets 0 .. the caller's net count are the top level's own,
+// and each instance's internal nets are appended after those, in the order the instances appear.
+// Nets joined by wires are merged into one canonical net, and components only ever refer to canonical nets.
+pub struct Netlist {
+  pub components: Vec<Component>,
+  // Capacitance given to each net by capacitance tables, not counting capacitor components. Summed onto canonical nets.
+  pub net_capacitance: Vec<u32>,
+  // Hierarchical names like "alu/adder3/carry". Empty for top level nets that were never named.
+  pub net_names: Vec<String>,
+  // The net each net was merged into, which is itself for canonical nets. Only meaningful once parsing is done.
+  pub canonical: Vec<Net>,
+  // Lower wins: names given closer to the top override names from inside instances, which override default names.
+  // Explicit names get their depth, and default names DEFAULT_NAME_PRIORITY plus their depth.
+  name_priority: Vec<u32>,
+  definitions: HashMap<u32, Definition>,
+  // Definitions currently being expanded, to catch a subcircuit that contains itself.
+  expanding: Vec<u32>,
+}
+
+// Every default name ranks below every explicit name, however deep the explicit one was given.
+const DEFAULT_NAME_PRIORITY: u32 = u32::MAX / 2;
+
+// Names are sent as one u32 per character.
+fn decode_name(codes: &[u32]) -> String {
+  codes.iter().map(|&code| std::char::from_u32(code).unwrap_or(std::char::REPLACEMENT_CHARACTER)).collect()
+}
+
+// Unpacks memory contents given as one u32 per word, padding the rest of the memory with Invalid.
+fn decode_contents(words: &[u32], address_bit_count: u32, word_size: u32) -> Vec<NetState> {
+  let mut contents: Vec<NetState> = vec![];
+  for num in words {
+    for bit_position in 0..word_size {
+      contents.push(
+        if (num >> bit_position) & 1 != 0 {
+          NetState::High
+        } else {
+          NetState::Low
+        }
+      );
+    }
+  }
+  while contents.len() < (1 << address_bit_count) * word_size as usize {
+    contents.push(NetState::Invalid);
+  }
+  contents
+}
+
+// Unpacks a count followed by that many (tick, byte) pairs starting at start, returning them and where they end.
+fn decode_arrivals(description: &[u32], start: usize) -> (Vec<(u32, u8)>, usize) {
+  let count = description[start] as usize;
+  let pairs = &description[start + 1 .. start + 1 + 2 * count];
+  (pairs.chunks(2).map(|pair| (pair[0], pair[1] as u8)).collect(), start + 1 + 2 * count)
+}
+
+impl Netlist {
+  pub fn parse(description: &[u32], net_count: u32) -> Netlist {
+    let mut netlist = Netlist {
+      components: Vec::new(),
+      net_capacitance: Vec::new(),
+      net_names: Vec::new(),
+      canonical: Vec::new(),
+      name_priority: Vec::new(),
+      definitions: HashMap::new(),
+      expanding: Vec::new(),
+    };
+    netlist.add_nets(net_count);
+    let top_level_nets: Vec<Net> = (0..net_count).collect();
+    netlist.parse_into(description, &top_level_nets, "");
+    netlist.merge_wired_nets();
+    netlist
+  }
+
+  pub fn net_count(&self) -> u32 {
+    self.net_names.len() as u32
+  }
+
+  // Allocates count fresh nets, returning the first.
+  fn add_nets(&mut self, count: u32) -> Net {
+    let first = self.net_count();
+    let new_count = (first + count) as usize;
+    self.net_capacitance.resize(new_count, 0);
+    self.net_names.resize(new_count, String::new());
+    self.name_priority.resize(new_count, u32::MAX);
+    self.canonical.extend(first .. first + count);
+    first
+  }
+
+  // While parsing, canonical is a union-find forest over nets joined by wires.
+  fn find(&mut self, net: Net) -> Net {
+    let mut root = net;
+    while self.canonical[root as usize] != root {
+      root = self.canonical[root as usize];
+    }
+    let mut node = net;
+    while node != root {
+      node = std::mem::replace(&mut self.canonical[node as usize], root);
+    }
+    root
+  }
+
+  fn union(&mut self, a: Net, b: Net) {
+    let (a, b) = (self.find(a), self.find(b));
+    self.canonical[a.max(b) as usize] = a.min(b);
+  }
+
+  // Picks the best named net of each wired group as its canonical net, with ties going to the lowest index,
+  // then points every component and all annotations at canonical nets.
+  fn merge_wired_nets(&mut self) {
+    let mut best: Vec<Net> = (0..self.net_count()).collect();
+    for net in 0..self.net_count() {
+      let root = self.find(net) as usize;
+      if self.name_priority[net as usize] < self.name_priority[best[root] as usize] {
+        best[root] = net;
+      }
+    }
+    for net in 0..self.net_count() {
+      self.canonical[net as usize] = best[self.canonical[net as usize] as usize];
+    }
+    for net in 0..self.net_count() as usize {
+      let canonical = self.canonical[net] as usize;
+      if canonical != net {
+        self.net_capacitance[canonical] += std::mem::take(&mut self.net_capacitance[net]);
+      }
+    }
+    let canonical = &self.canonical;
+    for component in self.components.iter_mut() {
+      component.map_nets(&|net| canonical[net as usize]);
+    }
+  }
+
+  fn set_name(&mut self, net: Net, name: String, priority: u32) {
+    if priority < self.name_priority[net as usize] {
+      self.net_names[net as usize] = name;
+      self.name_priority[net as usize] = priority;
+    }
+  }
+
+  fn instantiate(&mut self, definition_id: u32, ports: Vec<Net>, path: &str) {
+    if self.expanding.contains(&definition_id) {
+      panic!("Subcircuit {} contains itself, at {}", definition_id, path);
+    }
+    let definition = &self.definitions[&definition_id];
+    let body = definition.body.clone();
+    let internal_count = definition.local_net_count - definition.port_count;
+    let first_internal = self.add_nets(internal_count);
+    let depth = path.matches('/').count() as u32;
+    let mut local_nets = ports;
+    for net in first_internal .. first_internal + internal_count {
+      self.set_name(net, format!("{}{}", path, local_nets.len()), DEFAULT_NAME_PRIORITY + depth);
+      local_nets.push(net);
+    }
+    self.expanding.push(definition_id);
+    self.parse_into(&body, &local_nets, path);
+    self.expanding.pop();
+  }
+
+  // Parses description, in which net n means nets[n], prefixing any names with path.
+  fn parse_into(&mut self, description: &[u32], nets: &[Net], path: &str) {
+    let depth = path.matches('/').count() as u32;
+    let mut components: Vec<Component> = Vec::new();
+    let mut i: usize = 0;
+    while i < description.len() {
+      match description[i] {
+        1 => {
+          let is_pfet = description[i + 1] == 1;
+          let gate = description[i + 2];
+          let drain = description[i + 3];
+          let source = description[i + 4];
+          //components_by_output_net[drain as usize].push(Component::Fet{
+          components.push(Component::Fet{is_pfet, gate, drain, source, size: FetSize::DEFAULT});
+          //children[gate as usize].push(drain);
+          //children[source as usize].push(drain);
+          i += 5;
+        }
+        2 => {
+          let net = description[i + 1];
+          let repeat = description[i + 2] != 0;
+          let length = description[i + 3];
+          let pattern = description[i + 4 .. i + 4 + length as usize].iter().map(parse_pattern_var).collect();
+          //components_by_output_net[net as usize].push(Component::Signal{pattern, repeat, net});
+          components.push(Component::Signal{pattern, repeat, net, timing: None});
+          i += 4 + length as usize;
+        }
+        3 => {
+          let is_pull_down = description[i + 1] == 1;
+          let net = description[i + 2];
+          components.push(Component::PullResistor{is_pull_down, net});
+          i += 3;
+        }
+        // Opcode 5 is the same as 4, but with a read latency, setup ticks and hold ticks after write_enable_net.
+        4 | 5 => {
+          let address_bit_count = description[i + 1];
+          let word_size = description[i + 2];
+          let write_enable_net = description[i + 3];
+          let mut idx = i + 4;
+          let timing = match description[i] {
+            5 => {
+              idx += 3;
+              Some(SramTiming::new(description[i + 4], description[i + 5], description[i + 6]))
+            }
+            _ => None,
+          };
+          let contents_length = description[idx];
+          idx += 1;
+          let contents = decode_contents(&description[idx .. idx + contents_length as usize], address_bit_count, word_size);
+          //let contents = description[idx .. idx + contents_length as usize].iter().cloned().collect();
+          idx += contents_length as usize;
+          let address_nets = description[idx .. idx + address_bit_count as usize].to_vec();
+          idx += address_bit_count as usize;
+          let bus_in_nets = description[idx .. idx + word_size as usize].to_vec();
+          idx += word_size as usize;
+          let bus_out_nets = description[idx .. idx + word_size as usize].to_vec();
+          idx += word_size as usize;
+          components.push(Component::Sram{
+            address_bit_count,
+            word_size,
+            contents,
+            address_nets,
+            bus_in_nets,
+            bus_out_nets,
+            write_enable_net,
+            last_access: None,
+            reported_bad_write: None,
+            timing,
+          });
+          i = idx;
+        }
+        6 => {
+          // Layout: net_a, net_b, resistance in ohms. The resistance is kept in the format for analog use,
+          // but in the digital model all that matters is that the two nets are weakly coupled.
+          let net_a = description[i + 1];
+          let net_b = description[i + 2];
+          components.push(Component::Resistor{net_a, net_b});
+          i += 4;
+        }
+        7 => {
+          // Layout: net_a, net_b, capacitance in femtofarads. Both terminals see the full capacitance as load,
+          // though it never slows down a net held by a signal, like a rail.
+          let net_a = description[i + 1];
+          let net_b = description[i + 2];
+          let capacitance = description[i + 3];
+          components.push(Component::Capacitor{net_a, net_b, capacitance});
+          i += 4;
+        }
+        8 => {
+          // Per-net capacitance table. Layout: count, then count pairs of (net, capacitance in femtofarads).
+          let count = description[i + 1] as usize;
+          for pair in description[i + 2 .. i + 2 + 2 * count].chunks(2) {
+            self.net_capacitance[nets[pair[0] as usize] as usize] += pair[1];
+          }
+          i += 2 + 2 * count;
+        }
+        9 => {
+          // Layout: gate kind, delay in ticks, output net, input count, then the input nets. See gates::GateKind.
+          let kind = gates::GateKind::from_opcode(description[i + 1]);
+          let delay = description[i + 2];
+          let output = description[i + 3];
+          let input_count = description[i + 4] as usize;
+          let inputs = description[i + 5 .. i + 5 + input_count].to_vec();
+          components.push(Component::Gate(gates::Gate::new(kind, delay, inputs, output)));
+          i += 5 + input_count;
+        }
+        10 => {
+          // D flip-flop. Layout: d, clock, q, reset, enable, setup ticks, hold ticks. reset and enable may be NO_NET.
+          components.push(Component::Sequential(sequential::Sequential::flip_flop(
+            description[i + 1],
+            description[i + 2],
+            description[i + 3],
+            optional_net(description[i + 4]),
+            optional_net(description[i + 5]),
+            description[i + 6],
+            description[i + 7],
+          )));
+          i += 8;
+        }
+        11 => {
+          // D latch. Layout: d, enable, q, reset, setup ticks, hold ticks. reset may be NO_NET.
+          components.push(Component::Sequential(sequential::Sequential::latch(
+            description[i + 1],
+            description[i + 2],
+            description[i + 3],
+            optional_net(description[i + 4]),
+            description[i + 5],
+            description[i + 6],
+          )));
+          i += 7;
+        }
+        12 => {
+          // Subcircuit definition. Layout: definition id, port count, local net count, body length, then the body,
+          // which is a description of its own over local nets 0 .. local net count, the first port count of which are the ports.
+          if !path.is_empty() {
+            panic!("Subcircuit definitions must be at the top level, found one inside {}", path);
+          }
+          let definition_id = description[i + 1];
+          let port_count = description[i + 2];
+          let local_net_count = description[i + 3];
+          let body_length = description[i + 4] as usize;
+          let body = Rc::new(description[i + 5 .. i + 5 + body_length].to_vec());
+          self.definitions.insert(definition_id, Definition { port_count, local_net_count, body });
+          i += 5 + body_length;
+        }
+        13 => {
+          // Subcircuit instance. Layout: definition id, name length, name, then one net per port of the definition.
+          let definition_id = description[i + 1];
+          let name_length = description[i + 2] as usize;
+          let name = decode_name(&description[i + 3 .. i + 3 + name_length]);
+          i += 3 + name_length;
+          let port_count = match self.definitions.get(&definition_id) {
+            Some(definition) => definition.port_count as usize,
+            None => panic!("Instance {}{} of undefined subcircuit {}", path, name, definition_id),
+          };
+          let ports = description[i .. i + port_count].iter().map(|net| nets[*net as usize]).collect();
+          self.instantiate(definition_id, ports, &format!("{}{}/", path, name));
+          i += port_count;
+        }
+        14 => {
+          // Net name. Layout: net, name length, name.
+          let net = nets[description[i + 1] as usize];
+          let name_length = description[i + 2] as usize;
+          let name = decode_name(&description[i + 3 .. i + 3 + name_length]);
+          self.set_name(net, format!("{}{}", path, name), depth);
+          i += 3 + name_length;
+        }
+        15 => {
+          // Wire, joining nets into one. Layout: net count, nets.
+          let count = description[i + 1] as usize;
+          for pair in description[i + 2 .. i + 2 + count].windows(2) {
+            self.union(nets[pair[0] as usize], nets[pair[1] as usize]);
+          }
+          i += 2 + count;
+        }
+        16 => {
+          // Signal with its own timing. Layout: net, repeat, period, phase, duty, rest, length, pattern.
+          // A duty of 0 drives each value for its whole period, and otherwise rest is a pattern var driven for the remainder.
+          let net = description[i + 1];
+          let repeat = description[i + 2] != 0;
+          let timing = SignalTiming {
+            period: description[i + 3].max(1),
+            phase: description[i + 4],
+            duty: match description[i + 5] {
+              0 => None,
+              duty => Some(duty),
+            },
+            rest: parse_pattern_var(&description[i + 6]),
+          };
+          let length = description[i + 7] as usize;
+          let pattern = description[i + 8 .. i + 8 + length].iter().map(parse_pattern_var).collect();
+          components.push(Component::Signal{pattern, repeat, net, timing: Some(timing)});
+          i += 8 + length;
+        }
+        17 => {
+          // Signal as a list of edges. Layout: net, edge count, then (tick, pattern var) pairs, with ticks counted
+          // in simulation ticks rather than clock periods. Each value is driven until the next edge.
+          let net = description[i + 1];
+          let count = description[i + 2] as usize;
+          let mut edges: Vec<(u32, _)> = description[i + 3 .. i + 3 + 2 * count].chunks(2)
+            .map(|pair| (pair[0], parse_pattern_var(&pair[1])))
+            .collect();
+          edges.sort_by_key(|(tick, _)| *tick);
+          components.push(Component::EdgeSignal{edges, net});
+          i += 3 + 2 * count;
+        }
+        18 => {
+          // Pseudo-random stimulus. Layout: seed, period, phase, net count, nets. See stimulus::Lfsr.
+          let seed = description[i + 1];
+          let period = description[i + 2];
+          let phase = description[i + 3];
+          let count = description[i + 4] as usize;
+          let lfsr_nets = description[i + 5 .. i + 5 + count].to_vec();
+          components.push(Component::Lfsr(stimulus::Lfsr::new(lfsr_nets, seed, period, phase)));
+          i += 5 + count;
+        }
+        19 => {
+          // Handshake responder. Layout: req, ack, delay. See testbench::HandshakeResponder.
+          let responder = testbench::HandshakeResponder::new(description[i + 1], description[i + 2], description[i + 3]);
+          components.push(Component::HandshakeResponder(responder));
+          i += 4;
+        }
+        20 => {
+          // Triggered pulse. Layout: trigger, out, delay, width. See testbench::TriggeredPulse.
+          let (trigger, out) = (description[i + 1], description[i + 2]);
+          let pulse = testbench::TriggeredPulse::new(trigger, out, description[i + 3], description[i + 4]);
+          components.push(Component::TriggeredPulse(pulse));
+          i += 5;
+        }
+        21 => {
+          // Bus memory. Layout: address_bit_count, word_size, latency, req, ack, write, contents_length, contents,
+          // address nets, data_in nets, data_out nets. See testbench::BusMemory.
+          let address_bit_count = description[i + 1];
+          let word_size = description[i + 2];
+          let latency = description[i + 3];
+          let (req, ack, write) = (description[i + 4], description[i + 5], description[i + 6]);
+          let contents_length = description[i + 7] as usize;
+          let mut idx = i + 8;
+          let contents = decode_contents(&description[idx .. idx + contents_length], address_bit_count, word_size);
+          idx += contents_length;
+          let address_nets = description[idx .. idx + address_bit_count as usize].to_vec();
+          idx += address_bit_count as usize;
+          let data_in_nets = description[idx .. idx + word_size as usize].to_vec();
+          idx += word_size as usize;
+          let data_out_nets = description[idx .. idx + word_size as usize].to_vec();
+          idx += word_size as usize;
+          components.push(Component::BusMemory(testbench::BusMemory::new(
+            word_size,
+            latency,
+            contents,
+            req,
+            ack,
+            write,
+            address_nets,
+            data_in_nets,
+            data_out_nets,
+          )));
+          i = idx;
+        }
+        22 => {
+          // Memory-mapped console. Layout: address, address bit count, data bit count, write, address nets, data nets.
+          let address = description[i + 1];
+          let address_bit_count = description[i + 2] as usize;
+          let data_bit_count = description[i + 3] as usize;
+          let write = description[i + 4];
+          let address_nets = description[i + 5 .. i + 5 + address_bit_count].to_vec();
+          let data_nets = description[i + 5 + address_bit_count .. i + 5 + address_bit_count + data_bit_count].to_vec();
+          let console = peripherals::MappedConsole::new(address, address_nets, data_nets, write);
+          components.push(Component::Device(device::PluggedDevice::new(Box::new(console))));
+          i += 5 + address_bit_count + data_bit_count;
+        }
+        23 => {
+          // Serial console. Layout: tx, ticks per bit.
+          let console = peripherals::SerialConsole::new(description[i + 1], description[i + 2]);
+          components.push(Component::Device(device::PluggedDevice::new(Box::new(console))));
+          i += 3;
+        }
+        24 => {
+          // Memory-mapped keyboard. Layout: address, address bit count, read, ready (may be NO_NET), byte count,
+          // then (tick, byte) pairs giving when each byte arrives, then address nets and eight data nets.
+          let address = description[i + 1];
+          let address_bit_count = description[i + 2] as usize;
+          let read = description[i + 3];
+          let ready = optional_net(description[i + 4]);
+          let (arrivals, mut idx) = decode_arrivals(description, i + 5);
+          let address_nets = description[idx .. idx + address_bit_count].to_vec();
+          idx += address_bit_count;
+          let data_nets = description[idx .. idx + 8].to_vec();
+          idx += 8;
+          let keyboard = peripherals::MappedKeyboard::new(address, address_nets, data_nets, read, ready, arrivals);
+          components.push(Component::Device(device::PluggedDevice::new(Box::new(keyboard))));
+          i = idx;
+        }
+        25 => {
+          // Serial keyboard. Layout: rx, ticks per bit, byte count, then (tick, byte) pairs as for opcode 24.
+          let (arrivals, idx) = decode_arrivals(description, i + 3);
+          let keyboard = peripherals::SerialKeyboard::new(description[i + 1], description[i + 2], arrivals);
+          components.push(Component::Device(device::PluggedDevice::new(Box::new(keyboard))));
+          i = idx;
+        }
+        26 => {
+          // Display. Layout: mode, width, height, base address, address bit count, data bit count, write,
+          // address nets, data nets. See display::DisplayMode.
+          let mode = display::DisplayMode::from_opcode(description[i + 1]);
+          let (width, height, base_address) = (description[i + 2], description[i + 3], description[i + 4]);
+          let address_bit_count = description[i + 5] as usize;
+          let data_bit_count = description[i + 6] as usize;
+          let write = description[i + 7];
+          let address_nets = description[i + 8 .. i + 8 + address_bit_count].to_vec();
+          let data_nets = description[i + 8 + address_bit_count .. i + 8 + address_bit_count + data_bit_count].to_vec();
+          let display = display::Display::new(mode, width, height, base_address, address_nets, data_nets, write);
+          components.push(Component::Device(device::PluggedDevice::new(Box::new(display))));
+          i += 8 + address_bit_count + data_bit_count;
+        }
+        27 => {
+          // Timer. Layout: base address, address bit count, data bit count, write, read, clock, irq, address nets,
+          // data nets. read and clock may be NO_NET. See peripherals::Timer.
+          let base_address = description[i + 1];
+          let address_bit_count = description[i + 2] as usize;
+          let data_bit_count = description[i + 3] as usize;
+          let write = description[i + 4];
+          let read = optional_net(description[i + 5]);
+          let clock = optional_net(description[i + 6]);
+          let irq = description[i + 7];
+          let address_nets = description[i + 8 .. i + 8 + address_bit_count].to_vec();
+          let data_nets = description[i + 8 + address_bit_count .. i + 8 + address_bit_count + data_bit_count].to_vec();
+          let timer = peripherals::Timer::new(base_address, address_nets, data_nets, write, read, clock, irq);
+          components.push(Component::Device(device::PluggedDevice::new(Box::new(timer))));
+          i += 8 + address_bit_count + data_bit_count;
+        }
+        28 => {
+          // FIFO or LIFO. Layout: is_lifo, depth, word size, count bit count, push, pop, reset, full, empty,
+          // data_in nets, data_out nets, count nets. reset, full and empty may be NO_NET. See queue::Queue.
+          let is_lifo = description[i + 1] != 0;
+          let depth = description[i + 2];
+          let word_size = description[i + 3] as usize;
+          let count_bit_count = description[i + 4] as usize;
+          let (push, pop) = (description[i + 5], description[i + 6]);
+          let reset = optional_net(description[i + 7]);
+          let full = optional_net(description[i + 8]);
+          let empty = optional_net(description[i + 9]);
+          let mut idx = i + 10;
+          let data_in_nets = description[idx .. idx + word_size].to_vec();
+          idx += word_size;
+          let data_out_nets = description[idx .. idx + word_size].to_vec();
+          idx += word_size;
+          let count_nets = description[idx .. idx + count_bit_count].to_vec();
+          idx += count_bit_count;
+          let queue = queue::Queue::new(is_lifo, depth, push, pop, reset, full, empty, data_in_nets, data_out_nets, count_nets);
+          components.push(Component::Device(device::PluggedDevice::new(Box::new(queue))));
+          i = idx;
+        }
+        29 => {
+          // Sized FET. Layout: as for opcode 1, then width and length. Plain FETs are 1 by 1.
+          let is_pfet = description[i + 1] == 1;
+          let gate = description[i + 2];
+          let drain = description[i + 3];
+          let source = description[i + 4];
+          let size = FetSize { width: description[i + 5], length: description[i + 6] };
+          if size.width == 0 || size.length == 0 {
+            panic!("FET sizes must be nonzero, got {} by {}", size.width, size.length);
+          }
+          components.push(Component::Fet{is_pfet, gate, drain, source, size});
+          i += 7;
+        }
+        _ => panic!("Deserialization failure. Hit: {} at position {} out of length {}", description[i], i, description.len()),
+      }
+      if i >= description.len() {
+        panic!("Deserialization failure. Fell off end where there should be a sentinel.");
+      }
+      if description[i] != 123456789 {
+        panic!("Expected sentinel. Hit: {} at position {} out of length {}", description[i], i, description.len());
+      }
+      i += 1;
+      for mut component in components.drain(..) {
+        component.map_nets(&|net| nets[net as usize]);
+        self.components.push(component);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::testing::SENTINEL;
+  use super::Netlist;
+
+  fn encode(name: &str) -> Vec<u32> {
+    [&[name.len() as u32][..], &name.chars().map(|c| c as u32).collect::<Vec<u32>>()].concat()
+  }
+
+  fn definition(id: u32, port_count: u32, local_net_count: u32, body: &[u32]) -> Vec<u32> {
+    [&[12, id, port_count, local_net_count, body.len() as u32][..], body, &[SENTINEL]].concat()
+  }
+
+  fn instance(id: u32, name: &str, ports: &[u32]) -> Vec<u32> {
+    [&[13, id][..], &encode(name), ports, &[SENTINEL]].concat()
+  }
+
+  fn name(net: u32, name: &str) -> Vec<u32> {
+    [&[14, net][..], &encode(name), &[SENTINEL]].concat()
+  }
+
+  #[test]
+  fn names_from_deeper_instances_beat_default_names() {
+    // cpu leaves its internal net unnamed, and passes it to leaf, which names its port.
+    let description = [
+      definition(1, 1, 1, &name(0, "a")),
+      definition(0, 1, 2, &instance(1, "leaf", &[1])),
+      instance(0, "cpu", &[0]),
+      name(0, "top"),
+    ].concat();
+    assert_eq!(Netlist::parse(&description, 1).net_names, ["top", "cpu/leaf/a"]);
+  }
+
+  #[test]
+  fn wired_groups_prefer_deeper_explicit_names_to_default_names() {
+    // leaf wires its unnamed port to a named internal net, so cpu's default-named net joins a named one.
+    let description = [
+      definition(1, 1, 2, &[name(1, "x"), vec![15, 2, 0, 1, SENTINEL]].concat()),
+      definition(0, 1, 2, &instance(1, "leaf", &[1])),
+      instance(0, "cpu", &[0]),
+    ].concat();
+    let netlist = Netlist::parse(&description, 1);
+    assert_eq!(netlist.canonical, [0, 2, 2]);
+    assert_eq!(netlist.net_names[2], "cpu/leaf/x");
+  }
+}
@@ -0,0 +1,431 @@
+
+use std::collections::VecDeque;
+use super::{level_drive, pack_word, Device, DeviceTick, DriveType, Net, NetState, SimulationResults, WarningKind};
+
+// Built-in peripherals for CPU levels, implemented as devices so the main loop doesn't need to know about them.
+
+// A console at one address: each rising edge of write while the address nets hold that address prints the data byte.
+// With no address nets, every write prints.
+pub struct MappedConsole {
+  address: u32,
+  address_nets: Vec<Net>,
+  data_nets: Vec<Net>,
+  write: Net,
+  last_write: bool,
+  output: Vec<u8>,
+}
+
+impl MappedConsole {
+  pub fn new(address: u32, address_nets: Vec<Net>, data_nets: Vec<Net>, write: Net) -> MappedConsole {
+    MappedConsole { address, address_nets, data_nets, write, last_write: false, output: Vec::new() }
+  }
+}
+
+impl Device for MappedConsole {
+  fn inputs(&self) -> Vec<Net> {
+    [&[self.write], &self.address_nets[..], &self.data_nets[..]].concat()
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    Vec::new()
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    let write = match tick.inputs[0].level() {
+      Some(write) => write,
+      None => return,
+    };
+    let rising = write && !self.last_write;
+    self.last_write = write;
+    if !rising {
+      return;
+    }
+    let (address_states, data_states) = tick.inputs[1..].split_at(self.address_nets.len());
+    let (address, address_invalid_mask) = pack_word(address_states.iter());
+    if !self.address_nets.is_empty() && address & !address_invalid_mask != self.address & !address_invalid_mask {
+      return;
+    }
+    let (value, data_invalid_mask) = pack_word(data_states.iter());
+    match (address_invalid_mask, data_invalid_mask) {
+      (0, 0) => self.output.push(value as u8),
+      (0, _) => tick.warn_kind(WarningKind::PeripheralInvalidWrite, data_invalid_mask),
+      // The write might or might not have been for us.
+      _ => tick.warn_kind(WarningKind::PeripheralInvalidWrite, u32::MAX),
+    }
+  }
+
+  fn finish(&mut self, results: &mut SimulationResults) {
+    results.console_output.append(&mut self.output);
+  }
+}
+
+// A UART receiver on a TX net, for 8N1 serial at ticks_per_bit ticks per bit: the line idles High, and each byte is a
+// Low start bit, eight data bits least significant first, then a High stop bit. Bits are sampled in their middles.
+pub struct SerialConsole {
+  tx: Net,
+  ticks_per_bit: u32,
+  idle: bool,
+  // Tick the start bit was first seen Low, while receiving a byte.
+  start: Option<u32>,
+  byte: u32,
+  framing_error: bool,
+  output: Vec<u8>,
+}
+
+impl SerialConsole {
+  pub fn new(tx: Net, ticks_per_bit: u32) -> SerialConsole {
+    SerialConsole {
+      tx,
+      ticks_per_bit: ticks_per_bit.max(1),
+      idle: false,
+      start: None,
+      byte: 0,
+      framing_error: false,
+      output: Vec::new(),
+    }
+  }
+}
+
+impl Device for SerialConsole {
+  fn inputs(&self) -> Vec<Net> {
+    vec![self.tx]
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    Vec::new()
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    let line = tick.inputs[0];
+    let start = match self.start {
+      Some(start) => start,
+      None => {
+        // A byte starts when the line falls from idle.
+        match line {
+          NetState::High => self.idle = true,
+          NetState::Low if self.idle => {
+            self.start = Some(tick.t);
+            self.byte = 0;
+            self.framing_error = false;
+          }
+          _ => (),
+        }
+        return;
+      }
+    };
+    let elapsed = tick.t - start;
+    if elapsed % self.ticks_per_bit != self.ticks_per_bit / 2 {
+      return;
+    }
+    match (elapsed / self.ticks_per_bit, line) {
+      // A glitch rather than a start bit.
+      (0, NetState::Low) => (),
+      (0, _) => {
+        self.start = None;
+        self.idle = line == NetState::High;
+      }
+      (bit @ 1..=8, NetState::Low) => self.byte &= !(1 << (bit - 1)),
+      (bit @ 1..=8, NetState::High) => self.byte |= 1 << (bit - 1),
+      (1..=8, _) => self.framing_error = true,
+      (_, stop) => {
+        if stop == NetState::High && !self.framing_error {
+          self.output.push(self.byte as u8);
+        } else {
+          tick.warn_kind(WarningKind::UartFramingError, self.byte);
+        }
+        self.start = None;
+        self.idle = stop == NetState::High;
+      }
+    }
+  }
+
+  fn finish(&mut self, results: &mut SimulationResults) {
+    results.console_output.append(&mut self.output);
+  }
+}
+
+// Bytes from the host, each becoming available at its tick. Preloaded input just arrives at tick 0.
+struct InputQueue {
+  arrivals: VecDeque<(u32, u8)>,
+  available: VecDeque<u8>,
+}
+
+impl InputQueue {
+  fn new(mut arrivals: Vec<(u32, u8)>) -> InputQueue {
+    arrivals.sort_by_key(|(tick, _)| *tick);
+    InputQueue { arrivals: arrivals.into(), available: VecDeque::new() }
+  }
+
+  fn arrive(&mut self, t: u32) {
+    while let Some(&(tick, byte)) = self.arrivals.front() {
+      if tick > t {
+        break;
+      }
+      self.available.push_back(byte);
+      self.arrivals.pop_front();
+    }
+  }
+}
+
+// A keyboard with two registers: status at address (bit 0 set while a byte is waiting) and data at address + 1.
+// While read is High with a register's address on the address nets the register is driven onto the data nets,
+// and the falling edge of a read from data consumes the byte. ready, if connected, is High while a byte is waiting.
+pub struct MappedKeyboard {
+  address: u32,
+  address_nets: Vec<Net>,
+  data_nets: Vec<Net>,
+  read: Net,
+  ready: Option<Net>,
+  queue: InputQueue,
+  reading_data: bool,
+}
+
+impl MappedKeyboard {
+  pub fn new(
+    address: u32,
+    address_nets: Vec<Net>,
+    data_nets: Vec<Net>,
+    read: Net,
+    ready: Option<Net>,
+    arrivals: Vec<(u32, u8)>,
+  ) -> MappedKeyboard {
+    MappedKeyboard { address, address_nets, data_nets, read, ready, queue: InputQueue::new(arrivals), reading_data: false }
+  }
+}
+
+impl Device for MappedKeyboard {
+  fn inputs(&self) -> Vec<Net> {
+    [&[self.read], &self.address_nets[..]].concat()
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    let mut outputs = self.data_nets.clone();
+    outputs.extend(self.ready);
+    outputs
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    self.queue.arrive(tick.t);
+    let (address, address_invalid_mask) = pack_word(tick.inputs[1..].iter());
+    let register = match (tick.inputs[0].level(), address_invalid_mask) {
+      (Some(true), 0) => address.checked_sub(self.address).filter(|register| *register < 2),
+      _ => None,
+    };
+    if self.reading_data && tick.inputs[0] == NetState::Low {
+      self.queue.available.pop_front();
+    }
+    if register == Some(1) && !self.reading_data && self.queue.available.is_empty() {
+      tick.warn_kind(WarningKind::InputReadWhenEmpty, 0);
+    }
+    // A read that goes Invalid rather than Low isn't over yet.
+    match register {
+      Some(1) => self.reading_data = true,
+      _ if tick.inputs[0] != NetState::Invalid => self.reading_data = false,
+      _ => (),
+    }
+
+    let ready = !self.queue.available.is_empty();
+    let value = match register {
+      Some(0) => Some(ready as u8),
+      Some(_) => Some(self.queue.available.front().copied().unwrap_or(0)),
+      None => None,
+    };
+    let data_count = self.data_nets.len();
+    if let Some(value) = value {
+      for (bit_position, drive) in tick.outputs[..data_count].iter_mut().enumerate() {
+        *drive = level_drive((value >> bit_position) & 1 != 0);
+      }
+    }
+    if self.ready.is_some() {
+      tick.outputs[data_count] = level_drive(ready);
+    }
+  }
+}
+
+// A UART transmitter feeding a circuit's RX net, sending each byte as 8N1 serial as soon as it's available,
+// at ticks_per_bit ticks per bit. The line idles High, and is held idle for the first bit time so a receiver sees
+// idle before the first start bit.
+pub struct SerialKeyboard {
+  rx: Net,
+  ticks_per_bit: u32,
+  queue: InputQueue,
+  // The byte being sent and the tick its start bit began.
+  sending: Option<(u8, u32)>,
+}
+
+impl SerialKeyboard {
+  pub fn new(rx: Net, ticks_per_bit: u32, arrivals: Vec<(u32, u8)>) -> SerialKeyboard {
+    SerialKeyboard { rx, ticks_per_bit: ticks_per_bit.max(1), queue: InputQueue::new(arrivals), sending: None }
+  }
+}
+
+impl Device for SerialKeyboard {
+  fn inputs(&self) -> Vec<Net> {
+    Vec::new()
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    vec![self.rx]
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    self.queue.arrive(tick.t);
+    if let Some((_, start)) = self.sending {
+      // The stop bit is over.
+      if tick.t - start >= 10 * self.ticks_per_bit {
+        self.sending = None;
+      }
+    }
+    if self.sending.is_none() && tick.t >= self.ticks_per_bit {
+      self.sending = self.queue.available.pop_front().map(|byte| (byte, tick.t));
+    }
+    tick.outputs[0] = match self.sending {
+      Some((byte, start)) => match (tick.t - start) / self.ticks_per_bit {
+        0 => DriveType::Low,
+        bit @ 1..=8 => level_drive((byte >> (bit - 1)) & 1 != 0),
+        _ => DriveType::High,
+      },
+      None => DriveType::High,
+    };
+  }
+}
+
+// A countdown timer with three registers from base_address: reload (writing it also loads the count, and reading it
+// gives the current count), control (bit 0 enable, bit 1 periodic; reading it also gives the IRQ in bit 2), and
+// acknowledge (any write clears the IRQ). Registers are written on the rising edge of write, and read by holding
+// read High. While enabled the count goes down once per tick, or once per rising edge of clock if it's connected.
+// Reaching zero raises the IRQ until it's acknowledged, then reloads if periodic, or disables the timer if not.
+pub struct Timer {
+  base_address: u32,
+  address_nets: Vec<Net>,
+  data_nets: Vec<Net>,
+  write: Net,
+  read: Option<Net>,
+  clock: Option<Net>,
+  irq: Net,
+  reload: u32,
+  count: u32,
+  enabled: bool,
+  periodic: bool,
+  irq_pending: bool,
+  last_write: bool,
+  last_clock: bool,
+}
+
+impl Timer {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    base_address: u32,
+    address_nets: Vec<Net>,
+    data_nets: Vec<Net>,
+    write: Net,
+    read: Option<Net>,
+    clock: Option<Net>,
+    irq: Net,
+  ) -> Timer {
+    Timer {
+      base_address,
+      address_nets,
+      data_nets,
+      write,
+      read,
+      clock,
+      irq,
+      reload: 0,
+      count: 0,
+      enabled: false,
+      periodic: false,
+      irq_pending: false,
+      last_write: false,
+      last_clock: false,
+    }
+  }
+}
+
+impl Device for Timer {
+  fn inputs(&self) -> Vec<Net> {
+    let mut inputs = vec![self.write];
+    inputs.extend(self.read);
+    inputs.extend(self.clock);
+    [&inputs[..], &self.address_nets[..], &self.data_nets[..]].concat()
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    let mut outputs = self.data_nets.clone();
+    outputs.push(self.irq);
+    outputs
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    let control_count = 1 + self.read.is_some() as usize + self.clock.is_some() as usize;
+    let (control_states, bus_states) = tick.inputs.split_at(control_count);
+    let (address_states, data_states) = bus_states.split_at(self.address_nets.len());
+    let (address, address_invalid_mask) = pack_word(address_states.iter());
+    let register = match address_invalid_mask {
+      0 => address.checked_sub(self.base_address).filter(|register| *register < 3),
+      _ => None,
+    };
+    // With some address bits Invalid, whether any of our registers was addressed.
+    let might_be_addressed = address_invalid_mask != 0 && (0..3).any(|register| {
+      (self.base_address + register) & !address_invalid_mask == address & !address_invalid_mask
+    });
+
+    let count_event = match self.clock {
+      Some(_) => {
+        let clock = control_states[control_count - 1].level();
+        let rising = clock == Some(true) && !self.last_clock;
+        if let Some(clock) = clock {
+          self.last_clock = clock;
+        }
+        rising
+      }
+      None => true,
+    };
+    if count_event && self.enabled && self.count > 0 {
+      self.count -= 1;
+      if self.count == 0 {
+        self.irq_pending = true;
+        match self.periodic {
+          true => self.count = self.reload,
+          false => self.enabled = false,
+        }
+      }
+    }
+
+    if let Some(write) = control_states[0].level() {
+      if write && !self.last_write {
+        match (register, pack_word(data_states.iter())) {
+          (Some(0), (value, 0)) => {
+            self.reload = value;
+            self.count = value;
+          }
+          (Some(1), (value, 0)) => {
+            self.enabled = value & 1 != 0;
+            self.periodic = value & 2 != 0;
+          }
+          (Some(2), _) => self.irq_pending = false,
+          (Some(_), (_, invalid_mask)) => tick.warn_kind(WarningKind::PeripheralInvalidWrite, invalid_mask),
+          // The write might or might not have been for us.
+          (None, _) if might_be_addressed => tick.warn_kind(WarningKind::PeripheralInvalidWrite, u32::MAX),
+          (None, _) => (),
+        }
+      }
+      self.last_write = write;
+    }
+
+    let data_count = self.data_nets.len();
+    if self.read.is_some() && control_states[1].level() == Some(true) {
+      let value = match register {
+        Some(0) => Some(self.count),
+        Some(1) => Some(self.enabled as u32 | (self.periodic as u32) << 1 | (self.irq_pending as u32) << 2),
+        _ => None,
+      };
+      if let Some(value) = value {
+        for (bit_position, drive) in tick.outputs[..data_count].iter_mut().enumerate() {
+          *drive = level_drive((value >> bit_position) & 1 != 0);
+        }
+      }
+    }
+    tick.outputs[data_count] = level_drive(self.irq_pending);
+  }
+}
@@ -0,0 +1,131 @@
+
+use std::collections::VecDeque;
+use super::{level_drive, pack_word, Device, DeviceTick, DriveType, Net, NetState, WarningKind};
+
+// A behavioral FIFO, or a LIFO stack, of up to depth words. The rising edge of push stores data_in, and the rising
+// edge of pop drops the oldest (FIFO) or newest (LIFO) word; on a simultaneous edge the pop happens first.
+// data_out always shows the word that would be popped next, and is unknown while empty.
+// Pushing while full drops the word and popping while empty does nothing, and both are reported as warnings.
+pub struct Queue {
+  is_lifo: bool,
+  depth: usize,
+  push: Net,
+  pop: Net,
+  reset: Option<Net>,
+  full: Option<Net>,
+  empty: Option<Net>,
+  data_in_nets: Vec<Net>,
+  data_out_nets: Vec<Net>,
+  count_nets: Vec<Net>,
+  // (value, invalid mask) words, oldest first.
+  words: VecDeque<(u32, u32)>,
+  last_push: bool,
+  last_pop: bool,
+}
+
+impl Queue {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    is_lifo: bool,
+    depth: u32,
+    push: Net,
+    pop: Net,
+    reset: Option<Net>,
+    full: Option<Net>,
+    empty: Option<Net>,
+    data_in_nets: Vec<Net>,
+    data_out_nets: Vec<Net>,
+    count_nets: Vec<Net>,
+  ) -> Queue {
+    Queue {
+      is_lifo,
+      depth: depth as usize,
+      push,
+      pop,
+      reset,
+      full,
+      empty,
+      data_in_nets,
+      data_out_nets,
+      count_nets,
+      words: VecDeque::new(),
+      last_push: false,
+      last_pop: false,
+    }
+  }
+}
+
+// Whether a strobe rose this tick, judged against its last valid level.
+fn rising(state: NetState, last: &mut bool) -> bool {
+  match state.level() {
+    Some(level) => {
+      let rose = level && !*last;
+      *last = level;
+      rose
+    }
+    None => false,
+  }
+}
+
+impl Device for Queue {
+  fn inputs(&self) -> Vec<Net> {
+    let mut inputs = vec![self.push, self.pop];
+    inputs.extend(self.reset);
+    inputs.extend(&self.data_in_nets);
+    inputs
+  }
+
+  fn outputs(&self) -> Vec<Net> {
+    let mut outputs = self.data_out_nets.clone();
+    outputs.extend(self.full);
+    outputs.extend(self.empty);
+    outputs.extend(&self.count_nets);
+    outputs
+  }
+
+  fn step(&mut self, tick: &mut DeviceTick) {
+    let data_in_start = 2 + self.reset.is_some() as usize;
+    let pushed = rising(tick.inputs[0], &mut self.last_push);
+    let popped = rising(tick.inputs[1], &mut self.last_pop);
+    if popped {
+      let word = match self.is_lifo {
+        false => self.words.pop_front(),
+        true => self.words.pop_back(),
+      };
+      if word.is_none() {
+        tick.warn_kind(WarningKind::QueueUnderflow, 0);
+      }
+    }
+    if pushed {
+      let word = pack_word(tick.inputs[data_in_start..].iter());
+      match self.words.len() < self.depth {
+        true => self.words.push_back(word),
+        false => tick.warn_kind(WarningKind::QueueOverflow, word.0),
+      }
+    }
+    if self.reset.is_some() && tick.inputs[2].level() == Some(true) {
+      self.words.clear();
+    }
+
+    let next = match self.is_lifo {
+      false => self.words.front(),
+      true => self.words.back(),
+    };
+    let mut outputs = tick.outputs.iter_mut();
+    for (bit_position, drive) in outputs.by_ref().take(self.data_out_nets.len()).enumerate() {
+      *drive = match next {
+        Some((_, invalid_mask)) if invalid_mask >> bit_position & 1 != 0 => DriveType::Unknown,
+        Some((value, _)) if value >> bit_position & 1 != 0 => DriveType::High,
+        Some(_) => DriveType::Low,
+        None => DriveType::Unknown,
+      };
+    }
+    let flags = [(self.full, self.words.len() == self.depth), (self.empty, self.words.is_empty())];
+    for (_, flag) in flags.iter().filter(|(net, _)| net.is_some()) {
+      *outputs.next().unwrap() = level_drive(*flag);
+    }
+    for (bit_position, drive) in outputs.enumerate() {
+      *drive = level_drive(self.words.len() >> bit_position & 1 != 0);
+    }
+  }
+}
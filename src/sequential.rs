@@ -0,0 +1,140 @@
+
+use super::{DriveType, Net, NetState, WarningKind};
+
+// A D flip-flop (captures on the rising clock edge) or a D latch (transparent while its enable is high).
+// Violating setup or hold, or an unknown enable at the moment it matters, leaves the stored value unknown.
+pub struct Sequential {
+  is_latch: bool,
+  d: Net,
+  // The clock for a flip-flop, or the enable for a latch.
+  clock: Net,
+  pub q: Net,
+  // Active-high asynchronous reset to Low.
+  reset: Option<Net>,
+  // Active-high clock enable, for flip-flops only.
+  enable: Option<Net>,
+  setup_ticks: u32,
+  hold_ticks: u32,
+  stored: Option<bool>,
+  // The last level clock had while it was valid, so the Invalid tick of a transition doesn't count as an edge.
+  last_clock: Option<bool>,
+  last_d: NetState,
+  d_changed_at: u32,
+  // Tick of the last capturing (flip-flop) or closing (latch) edge, while its hold window is still open.
+  hold_window_start: Option<u32>,
+}
+
+impl Sequential {
+  pub fn flip_flop(d: Net, clock: Net, q: Net, reset: Option<Net>, enable: Option<Net>, setup_ticks: u32, hold_ticks: u32) -> Sequential {
+    Sequential {
+      is_latch: false,
+      d,
+      clock,
+      q,
+      reset,
+      enable,
+      setup_ticks,
+      hold_ticks,
+      stored: None,
+      last_clock: None,
+      last_d: NetState::Invalid,
+      d_changed_at: 0,
+      hold_window_start: None,
+    }
+  }
+
+  pub fn latch(d: Net, enable: Net, q: Net, reset: Option<Net>, setup_ticks: u32, hold_ticks: u32) -> Sequential {
+    Sequential { is_latch: true, ..Sequential::flip_flop(d, enable, q, reset, None, setup_ticks, hold_ticks) }
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    let mut nets = self.inputs();
+    nets.push(self.q);
+    nets
+  }
+
+  pub fn inputs(&self) -> Vec<Net> {
+    let mut inputs = vec![self.d, self.clock];
+    inputs.extend(self.reset);
+    inputs.extend(self.enable);
+    inputs
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    self.d = f(self.d);
+    self.clock = f(self.clock);
+    self.q = f(self.q);
+    self.reset = self.reset.map(f);
+    self.enable = self.enable.map(f);
+  }
+
+  // Returns the drive on q this tick. index is this element's position among flip-flops and latches, for warnings.
+  pub fn step(&mut self, t: u32, net_states: &[NetState], index: u32, warnings: &mut Vec<u32>) -> DriveType {
+    let d_state = net_states[self.d as usize];
+    if d_state != self.last_d {
+      self.last_d = d_state;
+      self.d_changed_at = t;
+    }
+
+    if let Some(hold_window_start) = self.hold_window_start {
+      if t - hold_window_start >= self.hold_ticks {
+        self.hold_window_start = None;
+      } else if self.d_changed_at == t {
+        warnings.extend_from_slice(&[t, WarningKind::SequentialHoldViolation as u32, index, t - hold_window_start]);
+        self.stored = None;
+        self.hold_window_start = None;
+      }
+    }
+
+    let clock = net_states[self.clock as usize].level();
+    let enabled = match self.enable {
+      Some(net) => net_states[net as usize].level(),
+      None => Some(true),
+    };
+    let edge = match self.is_latch {
+      false => clock == Some(true) && self.last_clock == Some(false),
+      true => clock == Some(false) && self.last_clock == Some(true),
+    };
+    if self.is_latch && clock == Some(true) {
+      self.stored = d_state.level();
+    }
+    if edge {
+      match enabled {
+        Some(true) => {
+          if !self.is_latch {
+            self.stored = d_state.level();
+          }
+          if t - self.d_changed_at < self.setup_ticks {
+            warnings.extend_from_slice(&[t, WarningKind::SequentialSetupViolation as u32, index, t - self.d_changed_at]);
+            self.stored = None;
+          }
+          self.hold_window_start = Some(t);
+        }
+        Some(false) => (),
+        // We don't know if we captured, so we only know the result if capturing wouldn't have changed anything.
+        None => if self.stored != d_state.level() {
+          self.stored = None;
+        },
+      }
+    }
+    if clock.is_some() {
+      self.last_clock = clock;
+    }
+
+    if let Some(reset) = self.reset {
+      match net_states[reset as usize].level() {
+        Some(true) => self.stored = Some(false),
+        Some(false) => (),
+        None => if self.stored != Some(false) {
+          self.stored = None;
+        },
+      }
+    }
+
+    match self.stored {
+      Some(false) => DriveType::Low,
+      Some(true) => DriveType::High,
+      None => DriveType::Unknown,
+    }
+  }
+}
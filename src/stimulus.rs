@@ -0,0 +1,60 @@
+
+use super::{merge_drives, DriveType, Net};
+
+// Drives a set of nets with pseudo-random bits from a 32-bit Galois LFSR (x^32 + x^22 + x^2 + x + 1), so that random
+// tests are reproducible from the description alone. Each period the LFSR is clocked once per net, and net i gets the
+// i-th output bit: bit number k * nets.len() + i of the sequence goes to net i in period k, counting from the seed.
+pub struct Lfsr {
+  nets: Vec<Net>,
+  state: u32,
+  period: u32,
+  phase: u32,
+  bits: Vec<bool>,
+}
+
+impl Lfsr {
+  // A seed of 0 would lock the LFSR up, so it's treated as 1.
+  pub fn new(nets: Vec<Net>, seed: u32, period: u32, phase: u32) -> Lfsr {
+    let bits = vec![false; nets.len()];
+    Lfsr { nets, state: seed.max(1), period: period.max(1), phase, bits }
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    self.nets.clone()
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    for net in self.nets.iter_mut() {
+      *net = f(*net);
+    }
+  }
+
+  fn next_bit(&mut self) -> bool {
+    let bit = self.state & 1 != 0;
+    self.state >>= 1;
+    if bit {
+      self.state ^= 0x8020_0003;
+    }
+    bit
+  }
+
+  // Merges this tick's drives into drives. The nets are undriven until the first period starts at the phase offset.
+  pub fn step(&mut self, t: u32, drives: &mut [DriveType]) {
+    let elapsed = match t.checked_sub(self.phase) {
+      Some(elapsed) => elapsed,
+      None => return,
+    };
+    if elapsed % self.period == 0 {
+      for i in 0..self.nets.len() {
+        self.bits[i] = self.next_bit();
+      }
+    }
+    for (net, bit) in self.nets.iter().zip(&self.bits) {
+      let drive = match bit {
+        false => DriveType::Low,
+        true => DriveType::High,
+      };
+      drives[*net as usize] = merge_drives(drives[*net as usize], drive);
+    }
+  }
+}
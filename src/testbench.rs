@@ -0,0 +1,206 @@
+
+use super::{drive_bus, level_drive, merge_drives, pack_word, DriveType, Net, NetState};
+
+// Environment components that watch the circuit and respond, rather than playing a fixed pattern.
+// They only react to valid levels, so an Invalid tick in the middle of a transition never counts as an edge.
+
+// The other side of a four-phase handshake: ack follows req, delay ticks after req settles at a new level.
+pub struct HandshakeResponder {
+  req: Net,
+  pub ack: Net,
+  delay: u32,
+  acked: bool,
+  changes_at: Option<u32>,
+}
+
+impl HandshakeResponder {
+  pub fn new(req: Net, ack: Net, delay: u32) -> HandshakeResponder {
+    HandshakeResponder { req, ack, delay, acked: false, changes_at: None }
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    vec![self.req, self.ack]
+  }
+
+  pub fn inputs(&self) -> Vec<Net> {
+    vec![self.req]
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    self.req = f(self.req);
+    self.ack = f(self.ack);
+  }
+
+  pub fn step(&mut self, t: u32, net_states: &[NetState]) -> DriveType {
+    match net_states[self.req as usize].level() {
+      // req went back before we answered, so there's nothing to answer.
+      Some(req) if req == self.acked => self.changes_at = None,
+      Some(_) if self.changes_at.is_none() => self.changes_at = Some(t + self.delay),
+      _ => (),
+    }
+    if self.changes_at == Some(t) {
+      self.acked = !self.acked;
+      self.changes_at = None;
+    }
+    level_drive(self.acked)
+  }
+}
+
+// Waits for trigger to rise, then after delay ticks drives out High for width ticks. out is Low otherwise.
+// A rising edge while a pulse is pending or in progress is ignored.
+pub struct TriggeredPulse {
+  trigger: Net,
+  pub out: Net,
+  delay: u32,
+  width: u32,
+  last_trigger: bool,
+  pulse_start: Option<u32>,
+}
+
+impl TriggeredPulse {
+  pub fn new(trigger: Net, out: Net, delay: u32, width: u32) -> TriggeredPulse {
+    TriggeredPulse { trigger, out, delay, width, last_trigger: false, pulse_start: None }
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    vec![self.trigger, self.out]
+  }
+
+  pub fn inputs(&self) -> Vec<Net> {
+    vec![self.trigger]
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    self.trigger = f(self.trigger);
+    self.out = f(self.out);
+  }
+
+  pub fn step(&mut self, t: u32, net_states: &[NetState]) -> DriveType {
+    if let Some(trigger) = net_states[self.trigger as usize].level() {
+      if trigger && !self.last_trigger && self.pulse_start.is_none() {
+        self.pulse_start = Some(t + self.delay);
+      }
+      self.last_trigger = trigger;
+    }
+    let pulsing = match self.pulse_start {
+      Some(start) if t >= start + self.width => {
+        self.pulse_start = None;
+        false
+      }
+      Some(start) => t >= start,
+      None => false,
+    };
+    level_drive(pulsing)
+  }
+}
+
+// A memory that answers bus cycles over a four-phase handshake, so the circuit doesn't need exact timing.
+// When req rises it samples the address and write, and latency ticks later it stores data_in (for a write) or starts
+// driving data_out (for a read), and raises ack. When req falls it drops ack and releases data_out.
+// A cycle with an Invalid address or write line is acknowledged but does nothing.
+pub struct BusMemory {
+  word_size: u32,
+  latency: u32,
+  pub contents: Vec<NetState>,
+  req: Net,
+  ack: Net,
+  write: Net,
+  address_nets: Vec<Net>,
+  data_in_nets: Vec<Net>,
+  data_out_nets: Vec<Net>,
+  // The cycle in progress: its address and whether it's a write (None if either was Invalid), and when it's answered.
+  cycle: Option<(Option<(u32, bool)>, u32)>,
+  acked: bool,
+}
+
+impl BusMemory {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    word_size: u32,
+    latency: u32,
+    contents: Vec<NetState>,
+    req: Net,
+    ack: Net,
+    write: Net,
+    address_nets: Vec<Net>,
+    data_in_nets: Vec<Net>,
+    data_out_nets: Vec<Net>,
+  ) -> BusMemory {
+    BusMemory {
+      word_size,
+      latency,
+      contents,
+      req,
+      ack,
+      write,
+      address_nets,
+      data_in_nets,
+      data_out_nets,
+      cycle: None,
+      acked: false,
+    }
+  }
+
+  pub fn address_bit_count(&self) -> u32 {
+    self.address_nets.len() as u32
+  }
+
+  pub fn word_size(&self) -> u32 {
+    self.word_size
+  }
+
+  pub fn nets(&self) -> Vec<Net> {
+    [&self.address_nets[..], &self.data_in_nets[..], &self.data_out_nets[..], &[self.req, self.ack, self.write]].concat()
+  }
+
+  pub fn inputs(&self) -> Vec<Net> {
+    [&self.address_nets[..], &self.data_in_nets[..], &[self.req, self.write]].concat()
+  }
+
+  pub fn map_nets(&mut self, f: &dyn Fn(Net) -> Net) {
+    for net in self.address_nets.iter_mut().chain(self.data_in_nets.iter_mut()).chain(self.data_out_nets.iter_mut()) {
+      *net = f(*net);
+    }
+    self.req = f(self.req);
+    self.ack = f(self.ack);
+    self.write = f(self.write);
+  }
+
+  fn word(&mut self, address: u32) -> &mut [NetState] {
+    let start = (address * self.word_size) as usize;
+    &mut self.contents[start .. start + self.word_size as usize]
+  }
+
+  // Merges this tick's drives on ack and data_out into drives.
+  pub fn step(&mut self, t: u32, net_states: &[NetState], drives: &mut [DriveType]) {
+    let req = net_states[self.req as usize].level();
+    if req == Some(true) && !self.acked && self.cycle.is_none() {
+      let (address, address_invalid_mask) = pack_word(self.address_nets.iter().map(|net| &net_states[*net as usize]));
+      let request = match (address_invalid_mask, net_states[self.write as usize].level()) {
+        (0, Some(is_write)) => Some((address, is_write)),
+        _ => None,
+      };
+      self.cycle = Some((request, t + self.latency));
+    }
+    if let Some((request, answers_at)) = self.cycle {
+      if !self.acked && t >= answers_at {
+        if let Some((address, true)) = request {
+          let data: Vec<NetState> = self.data_in_nets.iter().map(|net| net_states[*net as usize]).collect();
+          self.word(address).copy_from_slice(&data);
+        }
+        self.acked = true;
+      }
+    }
+    if req == Some(false) && self.acked {
+      self.acked = false;
+      self.cycle = None;
+    }
+
+    if let (true, Some((Some((address, false)), _))) = (self.acked, self.cycle) {
+      let word = self.word(address).to_vec();
+      drive_bus(drives, &self.data_out_nets, &word);
+    }
+    let ack = self.ack as usize;
+    drives[ack] = merge_drives(drives[ack], level_drive(self.acked));
+  }
+}